@@ -0,0 +1,80 @@
+use crate::math::*;
+use crate::image::luminance;
+use crate::checkpoint::{Decoder, Encoder};
+
+/// Stops sampling a pixel once the estimated error of its mean is small enough.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    /// Largest accepted relative standard error of the mean luminance of a pixel.
+    pub threshold: Float,
+    /// Number of samples every pixel gets before its error is estimated for the first time.
+    pub min_samples: usize
+}
+
+/// Running mean and variance of the luminance of the samples of a pixel, using Welford's algorithm.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PixelVariance {
+    count: usize,
+    mean: Float,
+    squared_deviations: Float
+}
+
+/// Added to the mean luminance when computing the relative error, so that dark pixels do not need unbounded
+/// numbers of samples.
+const DARK_OFFSET: Float = 0.1;
+/// The error is re-estimated after every this many samples, past the minimum.
+const CHECK_INTERVAL: usize = 8;
+
+impl AdaptiveSampling {
+    pub fn new(threshold: Float, min_samples: usize) -> AdaptiveSampling {
+        AdaptiveSampling { threshold: threshold, min_samples: min_samples }
+    }
+
+    /// Returns true if the pixel needs no more samples. The error is only checked at intervals, which keeps single
+    /// lucky samples from ending a pixel early.
+    pub fn converged(&self, pixel: &PixelVariance) -> bool {
+        pixel.count >= self.min_samples && (pixel.count - self.min_samples).is_multiple_of(CHECK_INTERVAL)
+            && pixel.error() <= self.threshold
+    }
+}
+
+impl PixelVariance {
+    pub fn new() -> PixelVariance {
+        PixelVariance::default()
+    }
+
+    pub(crate) fn encode(&self, out: &mut Encoder) {
+        out.u64(self.count as u64);
+        out.float(self.mean);
+        out.float(self.squared_deviations);
+    }
+
+    pub(crate) fn decode(input: &mut Decoder) -> Result<PixelVariance, String> {
+        Ok(PixelVariance { count: input.u64()? as usize, mean: input.float()?, squared_deviations: input.float()? })
+    }
+
+    pub fn add(&mut self, color: Vec3) {
+        let value = luminance(color);
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as Float;
+        self.squared_deviations += delta * (value - self.mean);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Unbiased estimate of the variance of a single sample.
+    pub fn variance(&self) -> Float {
+        if self.count > 1 { self.squared_deviations / (self.count - 1) as Float } else { 0.0 }
+    }
+
+    /// Standard error of the mean luminance, relative to the mean. Unknown, and `MAX_FLOAT`, below two samples.
+    pub fn error(&self) -> Float {
+        if self.count < 2 {
+            return MAX_FLOAT;
+        }
+        (self.variance() / self.count as Float).sqrt() / (self.mean + DARK_OFFSET)
+    }
+}
@@ -0,0 +1,211 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::math::*;
+use crate::hitable::HitRecord;
+use crate::image::*;
+use crate::checkpoint::{Decoder, Encoder};
+
+/// Arbitrary output variables: information about the first surface seen through a pixel, written next to the
+/// rendered image for compositing and denoising.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Aov {
+    /// Reflectance of the surface, averaged over all samples of the pixel.
+    Albedo,
+    /// World space shading normal, averaged over the samples that hit a surface.
+    Normal,
+    /// Distance from the camera along the viewing direction, averaged over the samples that hit a surface.
+    Depth,
+    /// World space position, averaged over the samples that hit a surface.
+    Position,
+    /// Index of the material plus one, taken from the first sample; 0 for the background.
+    MaterialId,
+    /// Id of the scene object, taken from the first sample; 0 for the background.
+    ObjectId
+}
+
+/// Accumulates the AOVs of the samples of a single pixel.
+#[derive(Copy, Clone, Default)]
+pub struct AovPixel {
+    samples: usize,
+    hits: usize,
+    albedo: Vec3,
+    normal: Vec3,
+    depth: Float,
+    position: Vec3,
+    ids: Option<(Float, Float)>
+}
+
+/// One image per selected AOV.
+pub struct AovBuffers {
+    aovs: Vec<Aov>,
+    images: Vec<Image>
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [Aov::Albedo, Aov::Normal, Aov::Depth, Aov::Position, Aov::MaterialId, Aov::ObjectId];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::MaterialId => "material",
+            Aov::ObjectId => "object"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    /// Names of the channels of the AOV in a multi-layer OpenEXR file, one per image component.
+    fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::MaterialId | Aov::ObjectId => &["id"]
+        }
+    }
+}
+
+impl fmt::Display for Aov {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl AovPixel {
+    pub fn new() -> AovPixel {
+        AovPixel { samples: 0, hits: 0, albedo: Vec3::zero(), normal: Vec3::zero(), depth: 0.0,
+            position: Vec3::zero(), ids: None }
+    }
+
+    /// Adds a sample whose camera ray first hit `hit`, if anything. `albedo` and `depth` describe the hit point.
+    pub fn add(&mut self, hit: Option<&HitRecord>, albedo: Vec3, depth: Float) {
+        self.samples += 1;
+        match hit {
+            Some(rec) => {
+                self.hits += 1;
+                self.albedo += albedo;
+                self.normal += rec.normal;
+                self.depth += depth;
+                self.position += rec.p;
+                if self.ids.is_none() {
+                    self.ids = Some(((rec.material + 1) as Float, rec.object as Float));
+                }
+            },
+            None if self.ids.is_none() => self.ids = Some((0.0, 0.0)),
+            None => ()
+        }
+    }
+
+    pub(crate) fn encode(&self, out: &mut Encoder) {
+        out.u64(self.samples as u64);
+        out.u64(self.hits as u64);
+        out.vec3(self.albedo);
+        out.vec3(self.normal);
+        out.float(self.depth);
+        out.vec3(self.position);
+        out.bool(self.ids.is_some());
+        let (material, object) = self.ids.unwrap_or((0.0, 0.0));
+        out.float(material);
+        out.float(object);
+    }
+
+    pub(crate) fn decode(input: &mut Decoder) -> Result<AovPixel, String> {
+        let mut pixel = AovPixel { samples: input.u64()? as usize, hits: input.u64()? as usize, albedo: input.vec3()?,
+            normal: input.vec3()?, depth: input.float()?, position: input.vec3()?, ids: None };
+        let has_ids = input.bool()?;
+        let ids = (input.float()?, input.float()?);
+        if has_ids {
+            pixel.ids = Some(ids);
+        }
+        Ok(pixel)
+    }
+
+    /// Returns the value of `aov` for the pixel. Scalar AOVs are stored in all three components.
+    fn value(&self, aov: Aov) -> Vec3 {
+        let hit_average = |sum: Vec3| if self.hits > 0 { (1.0 / self.hits as Float) * sum } else { Vec3::zero() };
+        let (material, object) = self.ids.unwrap_or((0.0, 0.0));
+        match aov {
+            Aov::Albedo if self.samples > 0 => (1.0 / self.samples as Float) * self.albedo,
+            Aov::Albedo => Vec3::zero(),
+            Aov::Normal => {
+                let normal = hit_average(self.normal);
+                if normal.length_squared() > 0.0 { Vec3::normalize(normal) } else { normal }
+            },
+            Aov::Depth => hit_average(Vec3::new(self.depth, self.depth, self.depth)),
+            Aov::Position => hit_average(self.position),
+            Aov::MaterialId => Vec3::new(material, material, material),
+            Aov::ObjectId => Vec3::new(object, object, object)
+        }
+    }
+}
+
+impl AovBuffers {
+    pub fn new(aovs: &[Aov], width: u32, height: u32) -> AovBuffers {
+        AovBuffers { aovs: aovs.to_vec(), images: aovs.iter().map(|_| Image::new(width, height)).collect() }
+    }
+
+    pub fn aovs(&self) -> &[Aov] {
+        &self.aovs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aovs.is_empty()
+    }
+
+    pub fn image(&self, aov: Aov) -> Option<&Image> {
+        self.aovs.iter().position(|&other| other == aov).map(|index| &self.images[index])
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, pixel: &AovPixel) {
+        for (aov, image) in self.aovs.iter().zip(self.images.iter_mut()) {
+            image.set(x, y, pixel.value(*aov));
+        }
+    }
+
+    /// Writes `aovs`, which must be part of the buffers, next to `path`. OpenEXR files get one layer per AOV in
+    /// addition to the R, G and B channels of `beauty`; other formats get a separate `<stem>.<aov>.<extension>` file
+    /// per AOV.
+    pub fn write(&self, path: &Path, aovs: &[Aov], beauty: &Image, pixel: ExrPixel) -> io::Result<()> {
+        let images: Vec<(Aov, &Image)> = aovs.iter().map(|&aov| match self.image(aov) {
+            Some(image) => (aov, image),
+            None => panic!("the {} AOV was not rendered", aov)
+        }).collect();
+        let format = match HdrFormat::from_path(path) {
+            Some(format) => format,
+            None => panic!("{} is not a floating point image file", path.display())
+        };
+        if format != HdrFormat::Exr {
+            for (aov, image) in &images {
+                image.write_hdr(&layer_path(path, *aov), format, pixel)?;
+            }
+            return Ok(());
+        }
+        let mut channels: Vec<Channel> = ["R", "G", "B"].iter().enumerate().map(|(component, name)|
+            Channel { name: name.to_string(), image: beauty, component: component, kind: pixel.into() }).collect();
+        for (aov, image) in images {
+            let kind = match aov {
+                Aov::MaterialId | Aov::ObjectId => ChannelKind::Uint,
+                _ => pixel.into()
+            };
+            for (component, name) in aov.channels().iter().enumerate() {
+                channels.push(Channel { name: format!("{}.{}", aov.name(), name), image: image, component: component,
+                    kind: kind });
+            }
+        }
+        write_exr(path, &channels)
+    }
+}
+
+/// Returns the path of the separate file for `aov`, e.g. `out/render.normal.pfm` for `out/render.pfm`.
+pub fn layer_path(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path.extension().map(|extension| extension.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!("{}.{}.{}", stem, aov.name(), extension))
+}
@@ -1,5 +1,20 @@
 use crate::math::*;
 
+/// Resolution-independent description of a camera.
+#[derive(Copy, Clone)]
+pub struct CameraSettings {
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    pub up: Vec3,
+    pub vertical_fov: Float,
+    pub aperture: Float,
+    /// Distance to the focal plane; defaults to the distance between `look_from` and `look_at`.
+    pub focus_dist: Option<Float>,
+    /// Times at which the shutter opens and closes; rays are distributed uniformly over this interval.
+    pub shutter_open: Float,
+    pub shutter_close: Float
+}
+
 pub struct Camera {
     origin: Vec3,
     lower_left_corner: Vec3,
@@ -7,12 +22,28 @@ pub struct Camera {
     vertical: Vec3,
     lens_radius: Float,
     u: Vec3,
-    v: Vec3
+    v: Vec3,
+    w: Vec3,
+    time0: Float,
+    time1: Float
+}
+
+impl CameraSettings {
+    pub fn new(look_from: Vec3, look_at: Vec3) -> CameraSettings {
+        CameraSettings { look_from: look_from, look_at: look_at, up: Vec3::new(0.0, 1.0, 0.0), vertical_fov: 40.0,
+            aperture: 0.0, focus_dist: None, shutter_open: 0.0, shutter_close: 0.0 }
+    }
 }
 
 impl Camera {
+    pub fn from_settings(settings: &CameraSettings, aspect_ratio: Float) -> Self {
+        let focus_dist = settings.focus_dist.unwrap_or_else(|| (settings.look_from - settings.look_at).length());
+        Camera::new(settings.look_from, settings.look_at, settings.up, settings.vertical_fov, aspect_ratio,
+            settings.aperture, focus_dist, settings.shutter_open, settings.shutter_close)
+    }
+
     pub fn new(look_from: Vec3, look_at: Vec3, up: Vec3, vertical_fov: Float,
-            aspect_ratio: Float, aperture: Float, focus_dist: Float) -> Self {
+            aspect_ratio: Float, aperture: Float, focus_dist: Float, time0: Float, time1: Float) -> Self {
         let theta = vertical_fov * PI / 180.0;
         let half_height = (theta / 2.0).tan();
         let half_width = aspect_ratio * half_height;
@@ -25,14 +56,23 @@ impl Camera {
         lower_left_corner: look_from - focus_dist * (half_width * u + half_height * v + w),
             horizontal: 2.0 * half_width * focus_dist * u,
             vertical: 2.0 * half_height * focus_dist * v,
-            lens_radius: aperture / 2.0, u: u, v: v
+            lens_radius: aperture / 2.0, u: u, v: v, w: w,
+            time0: time0, time1: time1
         }
     }
 
-    pub fn get_ray(&self, s: Float, t: Float) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+    /// Returns the ray through the point (`s`, `t`) of the image plane. `lens` picks the point on the lens and
+    /// `time` the point in the shutter interval, both from uniform random numbers.
+    pub fn get_ray(&self, s: Float, t: Float, lens: [Float; 2], time: Float) -> Ray {
+        let rd = self.lens_radius * sample_unit_disk(lens);
         let offset = rd.x() * self.u + rd.y() * self.v;
+        let time = self.time0 + time * (self.time1 - self.time0);
         Ray::new(self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset)
+            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset, time)
     }
-}
\ No newline at end of file
+
+    /// Distance of `p` from the camera along the viewing direction.
+    pub fn depth(&self, p: Vec3) -> Float {
+        Vec3::dot(self.origin - p, self.w)
+    }
+}
@@ -0,0 +1,265 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::math::*;
+use crate::settings::RenderSettings;
+use crate::framebuffer::Framebuffer;
+use crate::sampler::SamplerKind;
+
+/// Identifies checkpoint files, followed by the version of the format.
+const MAGIC: &[u8; 8] = b"RPTCKPT\0";
+const VERSION: u32 = 1;
+
+/// Why a checkpoint could not be resumed.
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    /// The file is not a checkpoint, or is damaged.
+    Format(String),
+    /// The checkpoint belongs to another scene or to settings that sample differently.
+    Mismatch(String)
+}
+
+/// What a render has to have in common with a checkpoint to continue from it: everything that decides which
+/// sample is taken where.
+#[derive(Clone, Debug, PartialEq)]
+struct RenderKey {
+    scene: u64,
+    width: u32,
+    height: u32,
+    /// The splats are kept per tile, so the tiles have to stay the same.
+    tile_size: u32,
+    seed: u64,
+    sampler: String,
+    /// The number of samples per pixel, which the strata of the stratified sampler depend on; 0 for other samplers.
+    strata: u64
+}
+
+/// Appends values to a byte buffer, little endian.
+pub(crate) struct Encoder {
+    bytes: Vec<u8>
+}
+
+/// Reads the values written by an `Encoder`.
+pub(crate) struct Decoder<'a> {
+    bytes: &'a [u8]
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(why) => write!(f, "{}", why),
+            CheckpointError::Format(why) => write!(f, "not a valid checkpoint: {}", why),
+            CheckpointError::Mismatch(why) => write!(f, "can't resume: {}", why)
+        }
+    }
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(why: io::Error) -> CheckpointError {
+        CheckpointError::Io(why)
+    }
+}
+
+impl RenderKey {
+    fn new(settings: &RenderSettings, scene_hash: u64) -> RenderKey {
+        let strata = if settings.sampler == SamplerKind::Stratified { settings.samples as u64 } else { 0 };
+        RenderKey { scene: scene_hash, width: settings.width, height: settings.height,
+            tile_size: settings.tile_size, seed: settings.seed, sampler: settings.sampler.name().to_string(), strata: strata }
+    }
+
+    fn encode(&self, out: &mut Encoder) {
+        out.u64(self.scene);
+        out.u32(self.width);
+        out.u32(self.height);
+        out.u32(self.tile_size);
+        out.u64(self.seed);
+        out.string(&self.sampler);
+        out.u64(self.strata);
+    }
+
+    fn decode(input: &mut Decoder) -> Result<RenderKey, String> {
+        Ok(RenderKey { scene: input.u64()?, width: input.u32()?, height: input.u32()?, tile_size: input.u32()?,
+            seed: input.u64()?, sampler: input.string()?, strata: input.u64()? })
+    }
+
+    /// Describes the first difference to `other`, the key of the checkpoint.
+    fn mismatch(&self, other: &RenderKey) -> Option<String> {
+        if self.scene != other.scene {
+            Some(String::from("the scene changed since the checkpoint was written"))
+        } else if (self.width, self.height) != (other.width, other.height) {
+            Some(format!("the checkpoint is {}x{} pixels, not {}x{}", other.width, other.height, self.width, self.height))
+        } else if self.tile_size != other.tile_size {
+            Some(format!("the checkpoint was rendered with a tile size of {}, not {}", other.tile_size, self.tile_size))
+        } else if self.seed != other.seed {
+            Some(format!("the checkpoint was rendered with seed {}, not {}", other.seed, self.seed))
+        } else if self.sampler != other.sampler {
+            Some(format!("the checkpoint was rendered with the {} sampler, not {}", other.sampler, self.sampler))
+        } else if self.strata != other.strata {
+            Some(format!("the stratified sampler of the checkpoint was set up for {} samples, not {}", other.strata,
+                self.strata))
+        } else {
+            None
+        }
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder { bytes: Vec::new() }
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn i128(&mut self, value: i128) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn float(&mut self, value: Float) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+
+    pub fn vec3(&mut self, value: Vec3) {
+        self.float(value.x());
+        self.float(value.y());
+        self.float(value.z());
+    }
+
+    pub fn string(&mut self, value: &str) {
+        self.u64(value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Decoder<'a> {
+        Decoder { bytes: bytes }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        if self.bytes.len() < N {
+            return Err(String::from("unexpected end of file"));
+        }
+        let (value, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(value.try_into().unwrap())
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn i128(&mut self) -> Result<i128, String> {
+        Ok(i128::from_le_bytes(self.take()?))
+    }
+
+    pub fn float(&mut self) -> Result<Float, String> {
+        Ok(Float::from_le_bytes(self.take()?))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        match self.take::<1>()? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            [value] => Err(format!("invalid boolean {}", value))
+        }
+    }
+
+    pub fn vec3(&mut self) -> Result<Vec3, String> {
+        Ok(Vec3::new(self.float()?, self.float()?, self.float()?))
+    }
+
+    pub fn string(&mut self) -> Result<String, String> {
+        let length = self.u64()? as usize;
+        if self.bytes.len() < length {
+            return Err(String::from("unexpected end of file"));
+        }
+        let (value, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        String::from_utf8(value.to_vec()).map_err(|_| String::from("invalid string"))
+    }
+
+    /// Returns true once everything has been read.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Reads a count of elements that each take at least `element_size` bytes, rejecting counts the rest of the
+    /// file can't hold before anything is allocated for them.
+    pub fn count(&mut self, element_size: usize) -> Result<usize, String> {
+        let count = self.u64()? as usize;
+        if count.saturating_mul(element_size) > self.bytes.len() {
+            return Err(String::from("unexpected end of file"));
+        }
+        Ok(count)
+    }
+}
+
+/// Writes the state of `framebuffer` to `path`, so that a render of the scene with hash `scene_hash` (see
+/// `Scene::hash`) and `settings` can later continue from it. The samplers need no state of their own: they derive
+/// every sample from the seed, the pixel and the sample count of the pixel, which is part of the framebuffer.
+/// The file is replaced atomically, so a crash while writing leaves the previous checkpoint intact.
+pub fn write(path: &Path, settings: &RenderSettings, scene_hash: u64, framebuffer: &Framebuffer) -> io::Result<()> {
+    let mut out = Encoder::new();
+    out.bytes.extend_from_slice(MAGIC);
+    out.u32(VERSION);
+    RenderKey::new(settings, scene_hash).encode(&mut out);
+    framebuffer.encode(&mut out);
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, &out.bytes)?;
+    fs::rename(&temporary, path)
+}
+
+/// Reads the framebuffer of the checkpoint at `path`, refusing it if it was not written for the same scene and
+/// sampling settings. The framebuffer keeps the filter it was rendered with.
+pub fn resume(path: &Path, settings: &RenderSettings, scene_hash: u64) -> Result<Framebuffer, CheckpointError> {
+    let bytes = fs::read(path)?;
+    let mut input = Decoder::new(&bytes);
+    let header = input.take::<8>().map_err(CheckpointError::Format)?;
+    if &header != MAGIC {
+        return Err(CheckpointError::Format(String::from("unknown file type")));
+    }
+    let version = input.u32().map_err(CheckpointError::Format)?;
+    if version != VERSION {
+        return Err(CheckpointError::Format(format!("unsupported version {}", version)));
+    }
+    let key = RenderKey::decode(&mut input).map_err(CheckpointError::Format)?;
+    if let Some(why) = RenderKey::new(settings, scene_hash).mismatch(&key) {
+        return Err(CheckpointError::Mismatch(why));
+    }
+    let framebuffer = Framebuffer::decode(&mut input).map_err(CheckpointError::Format)?;
+    if (framebuffer.width(), framebuffer.height()) != (settings.width, settings.height) {
+        return Err(CheckpointError::Mismatch(format!("the checkpoint holds {}x{} pixels, not {}x{}", framebuffer.width(),
+            framebuffer.height(), settings.width, settings.height)));
+    }
+    if framebuffer.filter() != &settings.filter() {
+        return Err(CheckpointError::Mismatch(format!("the checkpoint was rendered with a {} filter of radius {}",
+            framebuffer.filter().kind, framebuffer.filter().radius)));
+    }
+    if !input.is_empty() {
+        return Err(CheckpointError::Format(String::from("trailing data")));
+    }
+    Ok(framebuffer)
+}
@@ -0,0 +1,374 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use rusty_pt::settings::*;
+use rusty_pt::hitable::{BvhLayout, BvhSplit, MAX_DEPTH};
+use rusty_pt::aov::Aov;
+use rusty_pt::denoise::{DenoiseMethod, MIN_STRENGTH};
+use rusty_pt::sampler::SamplerKind;
+use rusty_pt::filter::FilterKind;
+use rusty_pt::image::{ExrPixel, HdrFormat, ToneMapOperator};
+
+const MAX_DIMENSION: u32 = 1 << 15;
+const OPTIONS: [&str; 42] = ["-W", "--width", "-H", "--height", "-r", "--resolution", "-s", "--samples",
+    "--adaptive", "--min-samples", "--spp-output", "--checkpoint", "--checkpoint-interval", "--resume", "--listen", "--worker", "--progressive", "--time-limit", "--noise-target",
+    "--sampler", "--filter", "--filter-radius", "-t", "--threads", "--tile-size", "-o", "--output", "--hdr-output", "--exr-pixel", "--tonemap", "--exposure",
+    "--white-point", "--aov-output", "--aovs", "--denoise",
+    "--denoise-strength", "--scene", "--seed", "--bvh", "--bvh-layout", "--bvh-leaf-size", "--bvh-max-depth"];
+/// Largest exposure compensation in stops, in both directions.
+const MAX_EXPOSURE: f32 = 32.0;
+
+pub enum Command {
+    Render(RenderSettings),
+    Help
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue { option: String, value: String, reason: String },
+    /// Two options that exclude each other were both given.
+    Conflict(String, String)
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::UnknownOption(option) => write!(f, "unknown option '{}'", option),
+            CliError::MissingValue(option) => write!(f, "option '{}' requires a value", option),
+            CliError::InvalidValue { option, value, reason } =>
+                write!(f, "invalid value '{}' for option '{}': {}", value, option, reason),
+            CliError::Conflict(first, second) => write!(f, "options '{}' and '{}' can't be used together", first, second)
+        }
+    }
+}
+
+pub fn usage(program: &str) -> String {
+    let defaults = RenderSettings::default();
+    let scenes: Vec<&str> = SceneKind::ALL.iter().map(|kind| kind.name()).collect();
+    let splits: Vec<&str> = BvhSplit::ALL.iter().map(|split| split.name()).collect();
+    let layouts: Vec<&str> = BvhLayout::ALL.iter().map(|layout| layout.name()).collect();
+    let hdr_formats: Vec<&str> = HdrFormat::ALL.iter().map(|format| format.extension()).collect();
+    let exr_pixels: Vec<&str> = ExrPixel::ALL.iter().map(|pixel| pixel.name()).collect();
+    let operators: Vec<&str> = ToneMapOperator::ALL.iter().map(|operator| operator.name()).collect();
+    let aovs: Vec<&str> = Aov::ALL.iter().map(|aov| aov.name()).collect();
+    let denoisers: Vec<&str> = DenoiseMethod::ALL.iter().map(|method| method.name()).collect();
+    let samplers: Vec<&str> = SamplerKind::ALL.iter().map(|kind| kind.name()).collect();
+    let filters: Vec<&str> = FilterKind::ALL.iter().map(|kind| kind.name()).collect();
+    format!("Usage: {} [OPTIONS]
+
+Options:
+  -W, --width <PIXELS>        image width (default: {})
+  -H, --height <PIXELS>       image height (default: {})
+  -r, --resolution <WxH>      image width and height, e.g. 640x480
+  -s, --samples <COUNT>       samples per pixel, the maximum with --adaptive, --time-limit or
+                              --noise-target (default: {})
+      --progressive <COUNT>   render in passes of COUNT samples per pixel over the whole image,
+                              updating the outputs after every pass
+      --time-limit <SECONDS>  stop starting new tiles after this much render time
+      --noise-target <ERROR>  stop after the pass that brings the mean relative error of the pixels
+                              below ERROR; both imply --progressive 16 unless it is given
+      --adaptive <ERROR>      stop sampling a pixel once the relative standard error of its mean
+                              luminance is below ERROR, e.g. 0.02
+      --min-samples <COUNT>   samples per pixel before --adaptive may stop (default: {})
+      --spp-output <PATH>     write the number of samples per pixel, relative to --samples for .png,
+                              as is for .{}
+      --checkpoint <PATH>     save the state of the render to PATH periodically and when it stops
+      --checkpoint-interval <SECONDS>
+                              render time between checkpoints (default: {})
+      --resume <PATH>         continue the render saved in a checkpoint; the scene, resolution,
+                              tile size, seed, sampler and filter must be the same
+      --listen <ADDR>         render on worker processes that connect to ADDR, e.g. 0.0.0.0:7878
+      --worker <ADDR>         render tiles for the coordinator at ADDR with --threads threads, for
+                              as long as the process runs; the scene and the files it references
+                              must be at the same paths as for the coordinator
+      --sampler <SAMPLER>     generator of the sample positions, one of: {}
+                              (default: {})
+      --filter <FILTER>       pixel reconstruction filter, one of: {} (default: {})
+      --filter-radius <PIX>   radius of the filter in pixels (default: 0.5 for box, 1 for tent,
+                              1.5 for gaussian, 2 for mitchell and lanczos)
+  -t, --threads <COUNT>       number of render threads (default: {})
+      --tile-size <PIXELS>    edge length of the square render tiles (default: {})
+  -o, --output <PATH>         output PNG file (default: {})
+      --hdr-output <PATH>     also write the unclamped image, with one of the
+                              extensions: .{}
+      --exr-pixel <TYPE>      channel type of .exr output, one of: {} (default: {})
+      --tonemap <OPERATOR>    tone mapping operator for the PNG output, one of: {}
+                              (default: {})
+      --exposure <STOPS>      exposure compensation applied before tone mapping (default: {})
+      --white-point <VALUE>   luminance mapped to white by reinhard-extended (default: image maximum)
+                              and hable (default: 11.2)
+      --aov-output <PATH>     also write AOVs of the first hit: as layers of an .exr file, or as
+                              separate <stem>.<aov>.pfm or .hdr files (.hdr drops negative values)
+      --aovs <LIST>           comma separated AOVs to write (default: all), of:
+                              {}
+      --denoise <METHOD>      filter the finished render, one of: {}, or none (default: none)
+      --denoise-strength <F>  color tolerance of the denoiser; larger values blur more (default: {})
+      --scene <NAME|FILE>     built-in scene to render, one of: {} (default: {}),
+                              or the path of a .scene file
+      --seed <NUMBER>         seed of the scene construction and the sampler; renders with the same
+                              seed are identical for any thread count (default: {})
+      --bvh <METHOD>          BVH construction method, one of: {} (default: {})
+      --bvh-layout <LAYOUT>   BVH memory layout and traversal, one of: {} (default: {})
+      --bvh-leaf-size <COUNT> maximum number of primitives in a BVH leaf (default: {})
+      --bvh-max-depth <DEPTH> maximum depth of the BVH (default: {})
+      --bvh-stats             print node count, depth and SAH cost of the scene BVH
+  -h, --help                  print this help text",
+        program, defaults.width, defaults.height, defaults.samples, defaults.min_samples, hdr_formats.join(", ."),
+        defaults.checkpoint_interval,
+        samplers.join(", "), defaults.sampler, filters.join(", "), defaults.filter, defaults.threads, defaults.tile_size,
+        defaults.output.display(), hdr_formats.join(", ."), exr_pixels.join(", "), defaults.exr_pixel,
+        operators.join(", "), defaults.tone_mapping.operator, defaults.tone_mapping.exposure, aovs.join(", "),
+        denoisers.join(", "), defaults.denoise_strength,
+        scenes.join(", "), defaults.scene, defaults.seed, splits.join(", "),
+        defaults.bvh.split, layouts.join(", "), defaults.bvh.layout, defaults.bvh.max_leaf_size, defaults.bvh.max_depth)
+}
+
+fn invalid(option: &str, value: &str, reason: &str) -> CliError {
+    CliError::InvalidValue { option: option.to_string(), value: value.to_string(), reason: reason.to_string() }
+}
+
+fn parse_number<T: FromStr + PartialOrd + fmt::Display>(option: &str, value: &str, min: T, max: T) -> Result<T, CliError> {
+    match value.parse::<T>() {
+        Ok(number) if number < min || number > max =>
+            Err(invalid(option, value, &format!("must be between {} and {}", min, max))),
+        Ok(number) => Ok(number),
+        Err(_) => Err(invalid(option, value, "not a valid number"))
+    }
+}
+
+fn parse_aovs(option: &str, value: &str) -> Result<Vec<Aov>, CliError> {
+    let mut aovs = Vec::new();
+    for name in value.split(',').map(str::trim) {
+        match Aov::from_name(name) {
+            Some(aov) if !aovs.contains(&aov) => aovs.push(aov),
+            Some(_) => return Err(invalid(option, value, &format!("'{}' is listed more than once", name))),
+            None => {
+                let names: Vec<&str> = Aov::ALL.iter().map(|aov| aov.name()).collect();
+                return Err(invalid(option, value, &format!("expected a list of: {}", names.join(", "))));
+            }
+        }
+    }
+    Ok(aovs)
+}
+
+fn parse_resolution(option: &str, value: &str) -> Result<(u32, u32), CliError> {
+    let mut parts = value.split(['x', 'X']);
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(width), Some(height), None) => Ok((
+            parse_number(option, width, 1, MAX_DIMENSION)?,
+            parse_number(option, height, 1, MAX_DIMENSION)?)),
+        _ => Err(invalid(option, value, "expected <WIDTH>x<HEIGHT>"))
+    }
+}
+
+/// Parses the command line arguments (excluding the program name) into render settings.
+///
+/// Options that are not given on the command line keep their value from `base`.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I, base: RenderSettings) -> Result<Command, CliError> {
+    let mut settings = base;
+    // the value of --min-samples, which is checked against --samples once all options are known
+    let mut min_samples = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // support both "--option value" and "--option=value"
+        let (option, inline_value) = match arg.find('=') {
+            Some(index) if arg.starts_with("--") => (arg[..index].to_string(), Some(arg[index + 1..].to_string())),
+            _ => (arg.clone(), None)
+        };
+        if option == "-h" || option == "--help" {
+            return Ok(Command::Help);
+        }
+        if option == "--bvh-stats" {
+            if let Some(value) = inline_value {
+                return Err(invalid(&option, &value, "option takes no value"));
+            }
+            settings.bvh_stats = true;
+            continue;
+        }
+        if !OPTIONS.contains(&option.as_str()) {
+            return Err(CliError::UnknownOption(option));
+        }
+        let value = match inline_value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(CliError::MissingValue(option))
+        };
+        match option.as_str() {
+            "-W" | "--width" => settings.width = parse_number(&option, &value, 1, MAX_DIMENSION)?,
+            "-H" | "--height" => settings.height = parse_number(&option, &value, 1, MAX_DIMENSION)?,
+            "-r" | "--resolution" => {
+                let (width, height) = parse_resolution(&option, &value)?;
+                settings.width = width;
+                settings.height = height;
+            },
+            "-s" | "--samples" => settings.samples = parse_number(&option, &value, 1, 1 << 24)?,
+            "--adaptive" => {
+                let threshold: f32 = parse_number(&option, &value, 0.0, 1.0)?;
+                if threshold <= 0.0 {
+                    return Err(invalid(&option, &value, "must be greater than 0"));
+                }
+                settings.adaptive_threshold = Some(threshold);
+            },
+            "--progressive" => settings.pass_samples = Some(parse_number(&option, &value, 1, 1 << 24)?),
+            "--time-limit" => {
+                let limit: f32 = parse_number(&option, &value, 0.0, 1e9)?;
+                if limit <= 0.0 {
+                    return Err(invalid(&option, &value, "must be greater than 0"));
+                }
+                settings.time_limit = Some(limit);
+            },
+            "--noise-target" => {
+                let target: f32 = parse_number(&option, &value, 0.0, 1.0)?;
+                if target <= 0.0 {
+                    return Err(invalid(&option, &value, "must be greater than 0"));
+                }
+                settings.noise_target = Some(target);
+            },
+            "--filter" => settings.filter = match FilterKind::from_name(&value) {
+                Some(kind) => kind,
+                None => {
+                    let filters: Vec<&str> = FilterKind::ALL.iter().map(|kind| kind.name()).collect();
+                    return Err(invalid(&option, &value, &format!("expected one of: {}", filters.join(", "))));
+                }
+            },
+            "--filter-radius" => {
+                let radius: f32 = parse_number(&option, &value, 0.0, 16.0)?;
+                if radius <= 0.0 {
+                    return Err(invalid(&option, &value, "must be greater than 0"));
+                }
+                settings.filter_radius = Some(radius);
+            },
+            "--sampler" => settings.sampler = match SamplerKind::from_name(&value) {
+                Some(kind) => kind,
+                None => {
+                    let samplers: Vec<&str> = SamplerKind::ALL.iter().map(|kind| kind.name()).collect();
+                    return Err(invalid(&option, &value, &format!("expected one of: {}", samplers.join(", "))));
+                }
+            },
+            "--min-samples" => {
+                settings.min_samples = parse_number(&option, &value, 1, 1 << 24)?;
+                min_samples = Some(value);
+            },
+            "--spp-output" => {
+                let path = PathBuf::from(&value);
+                if HdrFormat::from_path(&path).is_none() && !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+                    let extensions: Vec<&str> = HdrFormat::ALL.iter().map(|format| format.extension()).collect();
+                    return Err(invalid(&option, &value,
+                        &format!("output file must have one of the extensions: .png, .{}", extensions.join(", ."))));
+                }
+                settings.spp_output = Some(path);
+            },
+            "--checkpoint" => settings.checkpoint = Some(PathBuf::from(&value)),
+            "--checkpoint-interval" => {
+                let interval: f32 = parse_number(&option, &value, 0.0, 1e9)?;
+                if interval <= 0.0 {
+                    return Err(invalid(&option, &value, "must be greater than 0"));
+                }
+                settings.checkpoint_interval = interval;
+            },
+            "--resume" => settings.resume = Some(PathBuf::from(&value)),
+            "--listen" => settings.listen = Some(value),
+            "--worker" => settings.worker = Some(value),
+            "-t" | "--threads" => settings.threads = parse_number(&option, &value, 1, 1024)?,
+            "--tile-size" => settings.tile_size = parse_number(&option, &value, 1, MAX_DIMENSION)?,
+            "-o" | "--output" => {
+                let path = PathBuf::from(&value);
+                if !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+                    return Err(invalid(&option, &value, "output file must have a .png extension"));
+                }
+                settings.output = path;
+            },
+            "--hdr-output" => {
+                let path = PathBuf::from(&value);
+                if HdrFormat::from_path(&path).is_none() {
+                    let extensions: Vec<&str> = HdrFormat::ALL.iter().map(|format| format.extension()).collect();
+                    return Err(invalid(&option, &value,
+                        &format!("output file must have one of the extensions: .{}", extensions.join(", ."))));
+                }
+                settings.hdr_output = Some(path);
+            },
+            "--exr-pixel" => settings.exr_pixel = match ExrPixel::from_name(&value) {
+                Some(pixel) => pixel,
+                None => {
+                    let pixels: Vec<&str> = ExrPixel::ALL.iter().map(|pixel| pixel.name()).collect();
+                    return Err(invalid(&option, &value, &format!("expected one of: {}", pixels.join(", "))));
+                }
+            },
+            "--tonemap" => settings.tone_mapping.operator = match ToneMapOperator::from_name(&value) {
+                Some(operator) => operator,
+                None => {
+                    let operators: Vec<&str> = ToneMapOperator::ALL.iter().map(|operator| operator.name()).collect();
+                    return Err(invalid(&option, &value, &format!("expected one of: {}", operators.join(", "))));
+                }
+            },
+            "--exposure" => settings.tone_mapping.exposure = parse_number(&option, &value, -MAX_EXPOSURE, MAX_EXPOSURE)?,
+            "--white-point" => {
+                let white_point: f32 = parse_number(&option, &value, 0.0, f32::MAX)?;
+                if white_point <= 0.0 {
+                    return Err(invalid(&option, &value, "must be greater than 0"));
+                }
+                settings.tone_mapping.white_point = Some(white_point);
+            },
+            "--aov-output" => {
+                let path = PathBuf::from(&value);
+                if HdrFormat::from_path(&path).is_none() {
+                    let extensions: Vec<&str> = HdrFormat::ALL.iter().map(|format| format.extension()).collect();
+                    return Err(invalid(&option, &value,
+                        &format!("output file must have one of the extensions: .{}", extensions.join(", ."))));
+                }
+                settings.aov_output = Some(path);
+            },
+            "--aovs" => settings.aovs = parse_aovs(&option, &value)?,
+            "--denoise" => settings.denoise = match DenoiseMethod::from_name(&value) {
+                Some(method) => Some(method),
+                None if value == "none" => None,
+                None => {
+                    let methods: Vec<&str> = DenoiseMethod::ALL.iter().map(|method| method.name()).collect();
+                    return Err(invalid(&option, &value, &format!("expected one of: {}, none", methods.join(", "))));
+                }
+            },
+            "--denoise-strength" => {
+                settings.denoise_strength = parse_number(&option, &value, MIN_STRENGTH, 100.0)?;
+            },
+            "--scene" => settings.scene = match SceneSource::from_arg(&value) {
+                Some(source) => source,
+                None => {
+                    let scenes: Vec<&str> = SceneKind::ALL.iter().map(|kind| kind.name()).collect();
+                    return Err(invalid(&option, &value,
+                        &format!("expected one of: {}, or a path ending in .scene", scenes.join(", "))));
+                }
+            },
+            "--seed" => settings.seed = parse_number(&option, &value, 0, u64::MAX)?,
+            "--bvh" => settings.bvh.split = match BvhSplit::from_name(&value) {
+                Some(split) => split,
+                None => {
+                    let splits: Vec<&str> = BvhSplit::ALL.iter().map(|split| split.name()).collect();
+                    return Err(invalid(&option, &value, &format!("expected one of: {}", splits.join(", "))));
+                }
+            },
+            "--bvh-layout" => settings.bvh.layout = match BvhLayout::from_name(&value) {
+                Some(layout) => layout,
+                None => {
+                    let layouts: Vec<&str> = BvhLayout::ALL.iter().map(|layout| layout.name()).collect();
+                    return Err(invalid(&option, &value, &format!("expected one of: {}", layouts.join(", "))));
+                }
+            },
+            "--bvh-leaf-size" => settings.bvh.max_leaf_size = parse_number(&option, &value, 1, u16::MAX as usize)?,
+            "--bvh-max-depth" => settings.bvh.max_depth = parse_number(&option, &value, 1, MAX_DEPTH)?,
+            _ => return Err(CliError::UnknownOption(option))
+        }
+    }
+    if let Some(value) = min_samples {
+        if settings.adaptive_threshold.is_some() && settings.min_samples > settings.samples {
+            return Err(invalid("--min-samples", &value,
+                &format!("must be at most the number of samples per pixel, {}", settings.samples)));
+        }
+    }
+    // a worker renders for the coordinator it connects to, so it can't coordinate workers of its own
+    if settings.worker.is_some() && settings.listen.is_some() {
+        return Err(CliError::Conflict(String::from("--worker"), String::from("--listen")));
+    }
+    Ok(Command::Render(settings))
+}
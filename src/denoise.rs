@@ -0,0 +1,116 @@
+use std::fmt;
+use std::thread;
+
+use crate::math::*;
+use crate::image::Image;
+
+mod atrous;
+mod nlm;
+
+/// Post-process filters that remove Monte Carlo noise from the rendered radiance.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DenoiseMethod {
+    /// Edge-avoiding à-trous wavelet filter, guided by the albedo, normal and depth of the first hit.
+    Atrous,
+    /// Non-local means, comparing small patches of the image; the feature buffers only reject neighbours across
+    /// geometric edges.
+    Nlm
+}
+
+/// First-hit feature buffers that guide the filters, as produced by the AOVs of the same name.
+pub struct Features<'a> {
+    pub albedo: &'a Image,
+    pub normal: &'a Image,
+    pub depth: &'a Image
+}
+
+/// Smallest strength; below it the squared falloffs of the color weights underflow to 0 and the weights become NaN.
+pub const MIN_STRENGTH: Float = 1e-3;
+/// Channels with an albedo below this are not divided by it before filtering.
+const MIN_ALBEDO: Float = 0.01;
+/// Falloff of the feature weights; see `Features::weight`.
+const SIGMA_NORMAL: Float = 0.3;
+const SIGMA_ALBEDO: Float = 0.1;
+const SIGMA_DEPTH: Float = 0.05;
+
+impl DenoiseMethod {
+    pub const ALL: [DenoiseMethod; 2] = [DenoiseMethod::Atrous, DenoiseMethod::Nlm];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DenoiseMethod::Atrous => "atrous",
+            DenoiseMethod::Nlm => "nlm"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DenoiseMethod> {
+        DenoiseMethod::ALL.iter().copied().find(|method| method.name() == name)
+    }
+}
+
+impl fmt::Display for DenoiseMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl<'a> Features<'a> {
+    /// Weight of pixel `q` as a neighbour of pixel `p`, falling off with the difference of their features. Depth
+    /// differences are relative to the depth at `p` and are tolerated more at larger filter steps.
+    fn weight(&self, p: (u32, u32), q: (u32, u32), step: Float) -> Float {
+        let normal = (self.normal.get(p.0, p.1) - self.normal.get(q.0, q.1)).length_squared();
+        let albedo = (self.albedo.get(p.0, p.1) - self.albedo.get(q.0, q.1)).length_squared();
+        let depth_p = self.depth.get(p.0, p.1).x();
+        let depth = (depth_p - self.depth.get(q.0, q.1).x()).abs() / (depth_p.max(1e-3) * step);
+        (-normal / (SIGMA_NORMAL * SIGMA_NORMAL) - albedo / (SIGMA_ALBEDO * SIGMA_ALBEDO) - depth / SIGMA_DEPTH).exp()
+    }
+}
+
+/// Removes noise from `image` with `method`, using up to `threads` threads. Larger values of `strength` blur more;
+/// it is clamped to at least `MIN_STRENGTH`.
+///
+/// The image is divided by the albedo before filtering and multiplied with it afterwards, so that texture detail is
+/// kept even where the illumination is blurred.
+pub fn denoise(image: &Image, features: &Features, method: DenoiseMethod, strength: Float, threads: usize) -> Image {
+    let (width, height) = (image.width(), image.height());
+    let albedo = |x: u32, y: u32| {
+        let albedo = features.albedo.get(x, y);
+        let channel = |value: Float| if value > MIN_ALBEDO { value } else { 1.0 };
+        Vec3::new(channel(albedo.r()), channel(albedo.g()), channel(albedo.b()))
+    };
+    let irradiance = map_pixels(width, height, threads, |x, y| {
+        let (color, albedo) = (image.get(x, y), albedo(x, y));
+        Vec3::new(color.r() / albedo.r(), color.g() / albedo.g(), color.b() / albedo.b())
+    });
+    let strength = strength.max(MIN_STRENGTH);
+    let filtered = match method {
+        DenoiseMethod::Atrous => atrous::filter(&irradiance, features, strength, threads),
+        DenoiseMethod::Nlm => nlm::filter(&irradiance, features, strength, threads)
+    };
+    map_pixels(width, height, threads, |x, y| filtered.get(x, y) * albedo(x, y))
+}
+
+/// Compresses radiance into [0, 1) per channel, so that differences between bright pixels do not dominate the
+/// color weights of the filters.
+fn compress(color: Vec3) -> Vec3 {
+    let channel = |value: Float| { let value = value.max(0.0); value / (1.0 + value) };
+    Vec3::new(channel(color.r()), channel(color.g()), channel(color.b()))
+}
+
+/// Builds an image from the values of `pixel`, distributing bands of rows over `threads` threads.
+fn map_pixels<F: Fn(u32, u32) -> Vec3 + Sync>(width: u32, height: u32, threads: usize, pixel: F) -> Image {
+    let mut pixels = vec![Vec3::zero(); (width * height) as usize];
+    let rows_per_band = (height as usize).div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        for (band, chunk) in pixels.chunks_mut(rows_per_band * width as usize).enumerate() {
+            let pixel = &pixel;
+            scope.spawn(move || {
+                let top = (band * rows_per_band) as u32;
+                for (index, value) in chunk.iter_mut().enumerate() {
+                    *value = pixel(index as u32 % width, top + index as u32 / width);
+                }
+            });
+        }
+    });
+    Image::from_pixels(width, height, pixels)
+}
@@ -0,0 +1,48 @@
+use crate::math::*;
+use crate::image::Image;
+use crate::denoise::{compress, map_pixels, Features};
+
+/// Number of filter passes; the footprint of the last pass is 2^(ITERATIONS + 1) + 1 pixels wide.
+const ITERATIONS: u32 = 5;
+/// B3 spline weights for the offsets 0, 1 and 2 (times the step).
+const KERNEL: [Float; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+/// Falloff of the color weight in the first pass, for a strength of 1. It is halved in every pass, as the noise
+/// is reduced by the previous ones.
+const SIGMA_COLOR: Float = 0.4;
+
+/// The edge-avoiding à-trous wavelet transform of Dammertz et al., "Edge-Avoiding À-Trous Wavelet Transform for
+/// fast Global Illumination Filtering" (2010). Every pass applies a 5x5 B3 spline kernel with holes of increasing
+/// size, weighted by the differences of color and features.
+pub fn filter(image: &Image, features: &Features, strength: Float, threads: usize) -> Image {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let mut current = image.clone();
+    for iteration in 0..ITERATIONS {
+        let step = 1i64 << iteration;
+        let sigma_color = strength * SIGMA_COLOR / (1 << iteration) as Float;
+        let input = &current;
+        let next = map_pixels(image.width(), image.height(), threads, |x, y| {
+            let center = compress(input.get(x, y));
+            let (mut sum, mut total) = (Vec3::zero(), 0.0);
+            for dy in -2i64..=2 {
+                for dx in -2i64..=2 {
+                    let (qx, qy) = (x as i64 + dx * step, y as i64 + dy * step);
+                    if qx < 0 || qx >= width || qy < 0 || qy >= height {
+                        continue;
+                    }
+                    let (qx, qy) = (qx as u32, qy as u32);
+                    let color = input.get(qx, qy);
+                    let color_distance = (compress(color) - center).length_squared();
+                    let weight = KERNEL[dx.unsigned_abs() as usize] * KERNEL[dy.unsigned_abs() as usize]
+                        * (-color_distance / (sigma_color * sigma_color)).exp()
+                        * features.weight((x, y), (qx, qy), step as Float);
+                    sum += weight * color;
+                    total += weight;
+                }
+            }
+            // the center pixel always has a positive weight
+            (1.0 / total) * sum
+        });
+        current = next;
+    }
+    current
+}
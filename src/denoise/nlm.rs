@@ -0,0 +1,44 @@
+use crate::math::*;
+use crate::image::Image;
+use crate::denoise::{compress, map_pixels, Features};
+
+/// Neighbours within this distance of a pixel are compared with it.
+const SEARCH_RADIUS: i64 = 5;
+/// Patches are (2 * PATCH_RADIUS + 1)^2 pixels large.
+const PATCH_RADIUS: i64 = 1;
+/// Filtering parameter `h` for a strength of 1; patches whose mean squared difference is well above h^2 barely
+/// contribute.
+const H: Float = 0.08;
+
+/// Non-local means of Buades et al., "A non-local algorithm for image denoising" (2005). Every pixel becomes the
+/// weighted mean of its neighbours, weighted by how similar the patches around them are. The features are used to
+/// keep neighbours from across geometric edges out of the mean.
+pub fn filter(image: &Image, features: &Features, strength: Float, threads: usize) -> Image {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let compressed = map_pixels(image.width(), image.height(), threads, |x, y| compress(image.get(x, y)));
+    // pixels outside the image are clamped to the border for the patch comparison
+    let patch_pixel = |x: i64, y: i64| compressed.get(x.clamp(0, width - 1) as u32, y.clamp(0, height - 1) as u32);
+    let h_squared = strength * strength * H * H;
+    map_pixels(image.width(), image.height(), threads, |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        let (mut sum, mut total) = (Vec3::zero(), 0.0);
+        for qy in (y - SEARCH_RADIUS).max(0)..=(y + SEARCH_RADIUS).min(height - 1) {
+            for qx in (x - SEARCH_RADIUS).max(0)..=(x + SEARCH_RADIUS).min(width - 1) {
+                let mut distance = 0.0;
+                for oy in -PATCH_RADIUS..=PATCH_RADIUS {
+                    for ox in -PATCH_RADIUS..=PATCH_RADIUS {
+                        distance += (patch_pixel(x + ox, y + oy) - patch_pixel(qx + ox, qy + oy)).length_squared();
+                    }
+                }
+                let patch_size = (2 * PATCH_RADIUS + 1) * (2 * PATCH_RADIUS + 1);
+                let distance = distance / (3 * patch_size) as Float;
+                let weight = (-distance / h_squared).exp()
+                    * features.weight((x as u32, y as u32), (qx as u32, qy as u32), 1.0);
+                sum += weight * image.get(qx as u32, qy as u32);
+                total += weight;
+            }
+        }
+        // the center pixel always has a positive weight
+        (1.0 / total) * sum
+    })
+}
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::settings::RenderSettings;
+use crate::framebuffer::{Framebuffer, RenderTile};
+use crate::render::{image_tiles, stop_reason, Pass, Progress, RenderReport, Snapshot};
+use crate::render::{ProgressCallback, TileCallback};
+
+mod protocol;
+mod worker;
+use crate::distributed::protocol::{read_message, write_message, Message, MAX_HANDSHAKE_SIZE, MAX_MESSAGE_SIZE, VERSION};
+pub use crate::distributed::worker::work;
+
+/// How often workers tell the coordinator that they are still alive.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// A worker that has not sent anything for this long is considered dead, and its tiles are given to other workers.
+const WORKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something that happened to a worker of a distributed render.
+#[derive(Clone, Debug)]
+pub enum WorkerEvent {
+    Joined { address: SocketAddr, threads: usize },
+    /// The worker could not set up the render, or set it up with another scene.
+    Rejected { address: SocketAddr, reason: String },
+    /// The connection to the worker broke or timed out; its unfinished tiles were queued again.
+    Lost { address: SocketAddr, reason: String, requeued: usize }
+}
+
+type WorkerCallback = Box<dyn FnMut(&WorkerEvent) + Send>;
+
+/// Renders the image on worker processes, which connect over TCP and are handed tiles together with their current
+/// state. The coordinator runs the same passes as a `RenderSession`, and as every tile of a pass is rendered from the
+/// same state wherever it is rendered, the result is identical to a local render. Tiles of workers that disconnect
+/// or stop sending heartbeats are rendered by the remaining workers, and workers may join at any time.
+pub struct Coordinator {
+    settings: Arc<RenderSettings>,
+    scene_hash: u64,
+    /// The command line arguments the workers set up the render with.
+    job: Vec<String>,
+    framebuffer: Arc<RwLock<Framebuffer>>,
+    progress_callback: Option<ProgressCallback>,
+    tile_callback: Option<TileCallback>,
+    worker_callback: Option<WorkerCallback>
+}
+
+/// The tiles of the current pass, shared by the threads that talk to the workers.
+struct Queue {
+    /// Tiles that no worker has taken yet.
+    pending: Vec<RenderTile>,
+    /// Tiles that workers are rendering.
+    in_flight: usize,
+    pass: Option<Pass>,
+    next_id: u64,
+    /// Set once the last pass is done.
+    finished: bool
+}
+
+/// State shared by the coordinator and its worker threads.
+struct Shared {
+    scene_hash: u64,
+    job: Vec<String>,
+    framebuffer: Arc<RwLock<Framebuffer>>,
+    queue: Mutex<Queue>,
+    changed: Condvar
+}
+
+/// What the worker threads tell the coordinator.
+enum Update {
+    Tile(RenderTile),
+    Worker(WorkerEvent)
+}
+
+impl fmt::Display for WorkerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorkerEvent::Joined { address, threads } => write!(f, "worker {} joined with {} threads", address, threads),
+            WorkerEvent::Rejected { address, reason } => write!(f, "worker {} rejected: {}", address, reason),
+            WorkerEvent::Lost { address, reason, requeued } =>
+                write!(f, "worker {} lost ({}), {} tiles queued again", address, reason, requeued)
+        }
+    }
+}
+
+impl Queue {
+    /// Takes a tile of the current pass, unless there is none left or the pass has reached its deadline.
+    fn take(&mut self) -> Option<(u64, RenderTile, Pass)> {
+        self.expire();
+        let pass = self.pass?;
+        let tile = self.pending.pop()?;
+        self.in_flight += 1;
+        self.next_id += 1;
+        Some((self.next_id, tile, pass))
+    }
+
+    /// Drops the tiles that have not been started when the deadline has passed.
+    fn expire(&mut self) {
+        if self.pass.and_then(|pass| pass.deadline).is_some_and(|deadline| Instant::now() >= deadline) {
+            self.pending.clear();
+        }
+    }
+
+    fn pass_done(&mut self) -> bool {
+        self.expire();
+        self.pending.is_empty() && self.in_flight == 0
+    }
+}
+
+impl Coordinator {
+    /// Creates a coordinator for a render with `settings` of the scene with hash `scene_hash`; see `Scene::hash`.
+    /// Workers set up the render from the command line arguments in `job`.
+    pub fn new(settings: RenderSettings, scene_hash: u64, job: Vec<String>) -> Coordinator {
+        let framebuffer = Framebuffer::new(settings.width, settings.height, settings.filter());
+        Coordinator { settings: Arc::new(settings), scene_hash: scene_hash, job: job,
+            framebuffer: Arc::new(RwLock::new(framebuffer)), progress_callback: None, tile_callback: None,
+            worker_callback: None }
+    }
+
+    /// Creates a coordinator that continues the render in `framebuffer`; see `RenderSession::with_framebuffer`.
+    /// Fails if the framebuffer has another size or filter than `settings`.
+    pub fn with_framebuffer(settings: RenderSettings, scene_hash: u64, job: Vec<String>, framebuffer: Framebuffer)
+            -> Result<Coordinator, String> {
+        if (framebuffer.width(), framebuffer.height()) != (settings.width, settings.height) {
+            return Err(format!("the framebuffer is {}x{} pixels, but the settings are for {}x{}", framebuffer.width(),
+                framebuffer.height(), settings.width, settings.height));
+        }
+        if framebuffer.filter() != &settings.filter() {
+            return Err(String::from("the framebuffer was rendered with another filter than the settings"));
+        }
+        let mut coordinator = Coordinator::new(settings, scene_hash, job);
+        coordinator.framebuffer = Arc::new(RwLock::new(framebuffer));
+        Ok(coordinator)
+    }
+
+    /// Calls `callback` after tiles are done and at the end of every pass; see `RenderSession::on_progress`.
+    pub fn on_progress(&mut self, callback: impl FnMut(&Progress, &Snapshot) + Send + 'static) {
+        self.progress_callback = Some(Box::new(callback));
+    }
+
+    /// Calls `callback` with every tile that is done, on the same thread as the progress callback.
+    pub fn on_tile(&mut self, callback: impl FnMut(&RenderTile) + Send + 'static) {
+        self.tile_callback = Some(Box::new(callback));
+    }
+
+    /// Calls `callback` when workers join or are lost.
+    pub fn on_worker(&mut self, callback: impl FnMut(&WorkerEvent) + Send + 'static) {
+        self.worker_callback = Some(Box::new(callback));
+    }
+
+    /// Accepts workers on `listener` and renders until the render is finished, returning its report and
+    /// framebuffer. Waits for workers as long as there are none.
+    pub fn run(mut self, listener: TcpListener) -> io::Result<(RenderReport, Framebuffer)> {
+        // polled, so that the listener can be closed once the render is finished
+        listener.set_nonblocking(true)?;
+        let settings = Arc::clone(&self.settings);
+        let shared = Arc::new(Shared {
+            scene_hash: self.scene_hash,
+            job: self.job.clone(),
+            framebuffer: Arc::clone(&self.framebuffer),
+            queue: Mutex::new(Queue { pending: Vec::new(), in_flight: 0, pass: None, next_id: 0, finished: false }),
+            changed: Condvar::new()
+        });
+        let (updates, receiver) = mpsc::channel();
+        let accept_shared = Arc::clone(&shared);
+        let accept_updates = updates.clone();
+        let acceptor = thread::spawn(move || accept_workers(listener, accept_shared, accept_updates));
+
+        let tiles = image_tiles(&settings);
+        let tile_count = tiles.len();
+        let start_render = Instant::now();
+        let pass = Pass::new(&settings, start_render);
+        let mut pass_index = 0;
+        let stop_reason = loop {
+            {
+                let mut queue = shared.queue.lock().unwrap();
+                queue.pending = tiles.clone();
+                queue.pass = Some(pass);
+            }
+            shared.changed.notify_all();
+
+            let mut rendered_tiles = 0;
+            loop {
+                let update = match receiver.recv_timeout(Duration::from_millis(100)) {
+                    Ok(update) => Some(update),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => unreachable!("the coordinator holds a sender")
+                };
+                // the results of a worker are sent before its tiles count as done, so that none is missed here
+                let pass_done = shared.queue.lock().unwrap().pass_done();
+                let mut tiles_done = false;
+                for update in update.into_iter().chain(receiver.try_iter()) {
+                    match update {
+                        Update::Tile(tile) => {
+                            rendered_tiles += 1;
+                            tiles_done = true;
+                            if let Some(callback) = &mut self.tile_callback {
+                                callback(&tile);
+                            }
+                        },
+                        Update::Worker(event) => self.worker_event(&event)
+                    }
+                }
+                if tiles_done {
+                    self.report(Progress::new(pass_index + 1, rendered_tiles, tile_count, false), start_render);
+                }
+                if pass_done {
+                    break;
+                }
+            }
+            pass_index += 1;
+
+            self.report(Progress::new(pass_index, rendered_tiles, tile_count, true), start_render);
+            if let Some(reason) = stop_reason(&settings, &pass, &self.framebuffer.read().unwrap()) {
+                break reason;
+            }
+        };
+        let report = RenderReport::new(start_render.elapsed(), pass_index, stop_reason,
+            &self.framebuffer.read().unwrap());
+
+        shared.queue.lock().unwrap().finished = true;
+        shared.changed.notify_all();
+        drop(updates);
+        for handle in acceptor.join().unwrap() {
+            handle.join().unwrap();
+        }
+        for update in receiver.try_iter() {
+            if let Update::Worker(event) = update {
+                self.worker_event(&event);
+            }
+        }
+        let framebuffer = self.framebuffer.read().unwrap().clone();
+        Ok((report, framebuffer))
+    }
+
+    fn worker_event(&mut self, event: &WorkerEvent) {
+        if let Some(callback) = &mut self.worker_callback {
+            callback(event);
+        }
+    }
+
+    fn report(&mut self, mut progress: Progress, start_render: Instant) {
+        progress.complete(&self.settings, &self.framebuffer.read().unwrap(), start_render);
+        // the connections keep merging the results of the workers while the callback runs
+        if let Some(callback) = &mut self.progress_callback {
+            callback(&progress, &Snapshot::new(&self.framebuffer));
+        }
+    }
+}
+
+/// Accepts workers until the render is finished, and returns the threads that serve them.
+fn accept_workers(listener: TcpListener, shared: Arc<Shared>, updates: Sender<Update>) -> Vec<JoinHandle<()>> {
+    let mut handles = Vec::new();
+    while !shared.queue.lock().unwrap().finished {
+        match listener.accept() {
+            Ok((stream, address)) => {
+                let (shared, updates) = (Arc::clone(&shared), updates.clone());
+                handles.push(thread::spawn(move || serve_worker(stream, address, &shared, &updates)));
+            },
+            // nobody is waiting to connect, or the connection was aborted before it was accepted
+            Err(_) => thread::sleep(Duration::from_millis(50))
+        }
+    }
+    handles
+}
+
+/// Sets up the render on a worker and keeps it busy with tiles until the render is finished or the worker is lost.
+fn serve_worker(stream: TcpStream, address: SocketAddr, shared: &Shared, updates: &Sender<Update>) {
+    let (mut writer, mut reader, threads) = match handshake(stream, shared) {
+        Ok(connection) => connection,
+        Err(reason) => {
+            let _ = updates.send(Update::Worker(WorkerEvent::Rejected { address: address, reason: reason }));
+            return;
+        }
+    };
+    let _ = updates.send(Update::Worker(WorkerEvent::Joined { address: address, threads: threads }));
+
+    // the tiles the worker is rendering, by id, with the area of their splats
+    let mut taken = HashMap::new();
+    let result = loop {
+        // give the worker a tile for each of its threads, or wait for more work
+        let mut jobs = Vec::new();
+        {
+            let mut queue = shared.queue.lock().unwrap();
+            loop {
+                while taken.len() + jobs.len() < threads {
+                    match queue.take() {
+                        Some(job) => jobs.push(job),
+                        None => break
+                    }
+                }
+                if !taken.is_empty() || !jobs.is_empty() || queue.finished {
+                    break;
+                }
+                queue = shared.changed.wait(queue).unwrap();
+            }
+            if queue.finished && taken.is_empty() {
+                drop(queue);
+                let _ = write_message(&mut writer, &Message::Done);
+                return;
+            }
+        }
+        let mut sent = Ok(());
+        for (id, tile, pass) in jobs {
+            let (pixels, splats) = {
+                let framebuffer = shared.framebuffer.read().unwrap();
+                (framebuffer.read(&tile), framebuffer.read_splats(&tile))
+            };
+            taken.insert(id, (tile, splats.bounds()));
+            if sent.is_ok() {
+                sent = write_message(&mut writer, &Message::Tile { id: id, tile: tile, samples: pass.samples as u64,
+                    aovs: pass.aovs, pixels: pixels, splats: splats });
+            }
+        }
+        if let Err(why) = sent {
+            break why.to_string();
+        }
+
+        match read_message(&mut reader, MAX_MESSAGE_SIZE) {
+            Ok(Message::Result { id, pixels, splats }) => {
+                let (tile, bounds) = match taken.get(&id) {
+                    Some(&taken) => taken,
+                    None => break format!("result for unknown tile {}", id)
+                };
+                if pixels.len() != (tile.width * tile.height) as usize || splats.bounds() != bounds {
+                    break format!("result of the wrong size for tile {:?}", tile);
+                }
+                taken.remove(&id);
+                shared.framebuffer.write().unwrap().write(&tile, &pixels, splats);
+                let _ = updates.send(Update::Tile(tile));
+                shared.queue.lock().unwrap().in_flight -= 1;
+                shared.changed.notify_all();
+            },
+            Ok(Message::Heartbeat) => (),
+            Ok(_) => break String::from("unexpected message"),
+            Err(why) if why.kind() == io::ErrorKind::WouldBlock || why.kind() == io::ErrorKind::TimedOut =>
+                break String::from("timed out"),
+            Err(why) if why.kind() == io::ErrorKind::UnexpectedEof => break String::from("disconnected"),
+            Err(why) => break why.to_string()
+        }
+    };
+
+    // the unfinished tiles are rendered again from the state they were handed out with
+    let requeued = taken.len();
+    {
+        let mut queue = shared.queue.lock().unwrap();
+        queue.pending.extend(taken.values().map(|&(tile, _)| tile));
+        queue.in_flight -= requeued;
+    }
+    shared.changed.notify_all();
+    let _ = updates.send(Update::Worker(WorkerEvent::Lost { address: address, reason: result, requeued: requeued }));
+}
+
+/// Exchanges the greeting and the job with a new worker, and checks that it renders the same scene. Returns the
+/// connection and the number of threads of the worker.
+fn handshake(stream: TcpStream, shared: &Shared) -> Result<(TcpStream, BufReader<TcpStream>, usize), String> {
+    let setup = |stream: &TcpStream| -> io::Result<TcpStream> {
+        // accepted connections may inherit the non-blocking mode of the listener
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(WORKER_TIMEOUT))?;
+        stream.try_clone()
+    };
+    let mut writer = setup(&stream).map_err(|why| why.to_string())?;
+    let mut reader = BufReader::new(stream);
+    let threads = match read_message(&mut reader, MAX_HANDSHAKE_SIZE) {
+        Ok(Message::Hello { version, threads }) if version == VERSION && threads > 0 => threads as usize,
+        Ok(Message::Hello { version, .. }) if version != VERSION =>
+            return Err(format!("protocol version {}, expected {}", version, VERSION)),
+        Ok(_) => return Err(String::from("unexpected greeting")),
+        Err(why) => return Err(why.to_string())
+    };
+    write_message(&mut writer, &Message::Job { args: shared.job.clone() }).map_err(|why| why.to_string())?;
+    loop {
+        match read_message(&mut reader, MAX_HANDSHAKE_SIZE) {
+            Ok(Message::Heartbeat) => (),
+            Ok(Message::Ready { scene_hash }) if scene_hash == shared.scene_hash => break,
+            Ok(Message::Ready { .. }) => {
+                let _ = write_message(&mut writer, &Message::Done);
+                return Err(String::from("the worker loaded a different scene"));
+            },
+            Ok(Message::Failed { reason }) => return Err(reason),
+            Ok(_) => return Err(String::from("unexpected message")),
+            Err(why) => return Err(why.to_string())
+        }
+    }
+    Ok((writer, reader, threads))
+}
@@ -0,0 +1,151 @@
+use std::io::{self, Read, Write};
+
+use crate::checkpoint::{Decoder, Encoder};
+use crate::framebuffer::{PixelState, RenderTile, SplatBuffer};
+
+/// Changes whenever the messages change, so that workers and coordinators of different versions don't talk.
+pub const VERSION: u32 = 1;
+/// Largest accepted message; a tile of 256 x 256 pixels with a wide filter takes about 12 MB.
+pub const MAX_MESSAGE_SIZE: u64 = 1 << 30;
+/// Largest accepted message before a worker has been set up, so that a stray connection cannot make the
+/// coordinator buffer much.
+pub const MAX_HANDSHAKE_SIZE: u64 = 64 << 10;
+
+/// The messages between a coordinator and its workers. Every message is sent as its size in bytes followed by the
+/// encoded message.
+pub enum Message {
+    /// Worker: the first message after connecting, with the number of tiles the worker renders at once.
+    Hello { version: u32, threads: u32 },
+    /// Coordinator: the command line arguments the worker sets up the render with.
+    Job { args: Vec<String> },
+    /// Worker: the render is set up, for the scene of the given hash.
+    Ready { scene_hash: u64 },
+    /// Worker: the render could not be set up.
+    Failed { reason: String },
+    /// Coordinator: render `samples` more samples into the state of `tile`; AOVs only if `aovs` is set.
+    Tile { id: u64, tile: RenderTile, samples: u64, aovs: bool, pixels: Vec<PixelState>, splats: SplatBuffer },
+    /// Worker: the new state of the tile with `id`.
+    Result { id: u64, pixels: Vec<PixelState>, splats: SplatBuffer },
+    /// Worker: sent every second, so that the coordinator can tell a busy worker from a dead one.
+    Heartbeat,
+    /// Coordinator: there is no work (left) for the worker, which should disconnect.
+    Done
+}
+
+impl Message {
+    fn encode(&self, out: &mut Encoder) {
+        match self {
+            Message::Hello { version, threads } => {
+                out.u32(0);
+                out.u32(*version);
+                out.u32(*threads);
+            },
+            Message::Job { args } => {
+                out.u32(1);
+                out.u64(args.len() as u64);
+                for arg in args {
+                    out.string(arg);
+                }
+            },
+            Message::Ready { scene_hash } => {
+                out.u32(2);
+                out.u64(*scene_hash);
+            },
+            Message::Failed { reason } => {
+                out.u32(3);
+                out.string(reason);
+            },
+            Message::Tile { id, tile, samples, aovs, pixels, splats } => {
+                out.u32(4);
+                out.u64(*id);
+                for value in [tile.left, tile.top, tile.width, tile.height] {
+                    out.u32(value);
+                }
+                out.u64(*samples);
+                out.bool(*aovs);
+                encode_pixels(out, pixels);
+                splats.encode(out);
+            },
+            Message::Result { id, pixels, splats } => {
+                out.u32(5);
+                out.u64(*id);
+                encode_pixels(out, pixels);
+                splats.encode(out);
+            },
+            Message::Heartbeat => out.u32(6),
+            Message::Done => out.u32(7)
+        }
+    }
+
+    fn decode(input: &mut Decoder) -> Result<Message, String> {
+        let message = match input.u32()? {
+            0 => Message::Hello { version: input.u32()?, threads: input.u32()? },
+            1 => {
+                let count = input.count(8)?;
+                let mut args = Vec::with_capacity(count);
+                for _ in 0..count {
+                    args.push(input.string()?);
+                }
+                Message::Job { args: args }
+            },
+            2 => Message::Ready { scene_hash: input.u64()? },
+            3 => Message::Failed { reason: input.string()? },
+            4 => {
+                let id = input.u64()?;
+                let tile = RenderTile { left: input.u32()?, top: input.u32()?, width: input.u32()?, height: input.u32()? };
+                Message::Tile { id: id, tile: tile, samples: input.u64()?, aovs: input.bool()?,
+                    pixels: decode_pixels(input)?, splats: SplatBuffer::decode(input)? }
+            },
+            5 => Message::Result { id: input.u64()?, pixels: decode_pixels(input)?, splats: SplatBuffer::decode(input)? },
+            6 => Message::Heartbeat,
+            7 => Message::Done,
+            kind => return Err(format!("unknown message {}", kind))
+        };
+        if !input.is_empty() {
+            return Err(String::from("trailing data"));
+        }
+        Ok(message)
+    }
+}
+
+fn encode_pixels(out: &mut Encoder, pixels: &[PixelState]) {
+    out.u64(pixels.len() as u64);
+    for pixel in pixels {
+        pixel.encode(out);
+    }
+}
+
+fn decode_pixels(input: &mut Decoder) -> Result<Vec<PixelState>, String> {
+    let count = input.count(1)?;
+    let mut pixels = Vec::with_capacity(count);
+    for _ in 0..count {
+        pixels.push(PixelState::decode(input)?);
+    }
+    Ok(pixels)
+}
+
+pub fn write_message(stream: &mut impl Write, message: &Message) -> io::Result<()> {
+    let mut out = Encoder::new();
+    message.encode(&mut out);
+    let bytes = out.into_bytes();
+    stream.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    stream.write_all(&bytes)?;
+    stream.flush()
+}
+
+/// Reads the next message of at most `max_size` bytes. Malformed messages are reported as
+/// `io::ErrorKind::InvalidData`.
+pub fn read_message(stream: &mut impl Read, max_size: u64) -> io::Result<Message> {
+    let mut size = [0u8; 8];
+    stream.read_exact(&mut size)?;
+    let size = u64::from_le_bytes(size);
+    if size > max_size {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message of {} bytes is too large", size)));
+    }
+    // the buffer grows with the received bytes instead of the announced size
+    let mut bytes = Vec::new();
+    if stream.take(size).read_to_end(&mut bytes)? as u64 != size {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Message::decode(&mut Decoder::new(&bytes)).map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+}
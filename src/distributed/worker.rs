@@ -0,0 +1,139 @@
+use std::io::{self, BufReader};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::math::*;
+use crate::camera::Camera;
+use crate::settings::RenderSettings;
+use crate::scene::Scene;
+use crate::framebuffer::{PixelState, RenderTile, SplatBuffer};
+use crate::render::{render_tile, Control, Pass};
+use crate::distributed::HEARTBEAT_INTERVAL;
+use crate::distributed::protocol::{read_message, write_message, Message, MAX_MESSAGE_SIZE, VERSION};
+
+/// A tile received from the coordinator.
+struct Job {
+    id: u64,
+    tile: RenderTile,
+    pass: Pass,
+    pixels: Vec<PixelState>,
+    splats: SplatBuffer
+}
+
+fn protocol_error(why: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, why)
+}
+
+/// Connects to the coordinator at `address` and renders its tiles with `threads` render threads, until the
+/// coordinator has no more work. `load` sets up the render from the command line arguments the coordinator sends;
+/// its scene has to have the same hash as the one of the coordinator. Returns the number of tiles rendered.
+pub fn work<F>(address: &str, threads: usize, load: F) -> io::Result<usize>
+        where F: FnOnce(&[String]) -> Result<(RenderSettings, Scene), String> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    write_message(&mut *writer.lock().unwrap(), &Message::Hello { version: VERSION, threads: threads as u32 })?;
+
+    // heartbeats are sent from the start, as loading the scene may take a while
+    let (stop_heartbeat, heartbeat_stopped) = mpsc::channel::<()>();
+    let heartbeat_writer = Arc::clone(&writer);
+    let heartbeat = thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = heartbeat_stopped.recv_timeout(HEARTBEAT_INTERVAL) {
+            if write_message(&mut *heartbeat_writer.lock().unwrap(), &Message::Heartbeat).is_err() {
+                return;
+            }
+        }
+    });
+    let result = serve(BufReader::new(stream), &writer, threads, load);
+    drop(stop_heartbeat);
+    heartbeat.join().unwrap();
+    result
+}
+
+fn serve<F>(mut reader: BufReader<TcpStream>, writer: &Arc<Mutex<TcpStream>>, threads: usize, load: F)
+        -> io::Result<usize> where F: FnOnce(&[String]) -> Result<(RenderSettings, Scene), String> {
+    let args = match read_message(&mut reader, MAX_MESSAGE_SIZE)? {
+        Message::Job { args } => args,
+        Message::Done => return Ok(0),
+        _ => return Err(protocol_error(String::from("expected a job")))
+    };
+    let (mut settings, scene) = match load(&args) {
+        Ok(render) => render,
+        Err(reason) => {
+            write_message(&mut *writer.lock().unwrap(), &Message::Failed { reason: reason.clone() })?;
+            return Err(io::Error::other(reason));
+        }
+    };
+    settings.threads = threads;
+    write_message(&mut *writer.lock().unwrap(), &Message::Ready { scene_hash: scene.hash })?;
+
+    let camera = Arc::new(Camera::from_settings(&scene.camera, settings.width as Float / settings.height as Float));
+    let (settings, scene) = (Arc::new(settings), Arc::new(scene));
+    let control = Arc::new(Control::new());
+    let rendered = Arc::new(AtomicUsize::new(0));
+    let (jobs, receiver) = mpsc::channel();
+    let receiver = Arc::new(Mutex::new(receiver));
+    let mut thread_handles = Vec::new();
+    for _ in 0..threads {
+        let (settings, scene, camera) = (Arc::clone(&settings), Arc::clone(&scene), Arc::clone(&camera));
+        let (receiver, writer) = (Arc::clone(&receiver), Arc::clone(writer));
+        let (control, rendered) = (Arc::clone(&control), Arc::clone(&rendered));
+        thread_handles.push(thread::spawn(move || {
+            render_jobs(&receiver, &writer, &settings, &scene, &camera, &control, &rendered);
+        }));
+    }
+
+    let result = loop {
+        match read_message(&mut reader, MAX_MESSAGE_SIZE) {
+            Ok(Message::Tile { id, tile, samples, aovs, pixels, splats }) => {
+                if tile.width == 0 || tile.height == 0 || tile.left as u64 + tile.width as u64 > settings.width as u64
+                        || tile.top as u64 + tile.height as u64 > settings.height as u64
+                        || pixels.len() != (tile.width * tile.height) as usize {
+                    break Err(protocol_error(format!("invalid tile {:?}", tile)));
+                }
+                let pass = Pass { samples: samples as usize, deadline: None, aovs: aovs };
+                jobs.send(Job { id: id, tile: tile, pass: pass, pixels: pixels, splats: splats }).unwrap();
+            },
+            Ok(Message::Heartbeat) => (),
+            Ok(Message::Done) => break Ok(()),
+            Ok(_) => break Err(protocol_error(String::from("unexpected message"))),
+            Err(why) => break Err(why)
+        }
+    };
+    // without a coordinator the tiles in progress are of no use
+    if result.is_err() {
+        control.cancel();
+    }
+    drop(jobs);
+    for handle in thread_handles {
+        handle.join().unwrap();
+    }
+    result.map(|_| rendered.load(Ordering::Relaxed))
+}
+
+/// Renders the jobs from `receiver` and sends the results to the coordinator, until there are no more jobs.
+fn render_jobs(receiver: &Mutex<Receiver<Job>>, writer: &Mutex<TcpStream>, settings: &RenderSettings, scene: &Scene,
+        camera: &Camera, control: &Control, rendered: &AtomicUsize) {
+    let mut sampler = settings.sampler.create(settings.samples, settings.seed);
+    loop {
+        let job = receiver.lock().unwrap().recv();
+        let mut job = match job {
+            Ok(job) => job,
+            Err(_) => return
+        };
+        if !render_tile(settings, scene, camera, sampler.as_mut(), &job.tile, &mut job.pixels, &mut job.splats,
+                job.pass, control) {
+            return;
+        }
+        let result = Message::Result { id: job.id, pixels: job.pixels, splats: job.splats };
+        if write_message(&mut *writer.lock().unwrap(), &result).is_err() {
+            // the reading side notices the lost connection as well
+            control.cancel();
+            return;
+        }
+        rendered.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+
+use crate::math::*;
+
+/// The shapes of the pixel reconstruction filters.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterKind {
+    /// Constant weight; with a radius of half a pixel every sample only counts for its own pixel.
+    Box,
+    /// Weight falling off linearly with the distance.
+    Tent,
+    /// Gaussian with a standard deviation of a third of the radius, shifted down to reach zero at the radius.
+    Gaussian,
+    /// The cubic of Mitchell and Netravali (1988) with B = C = 1/3.
+    Mitchell,
+    /// Sinc windowed by a sinc stretched to the radius.
+    Lanczos
+}
+
+/// A separable reconstruction filter: the weight of a sample for a pixel is the product of the filter evaluated at
+/// the horizontal and vertical distances between the sample and the pixel center.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    /// Distance in pixels beyond which samples have no weight.
+    pub radius: Float
+}
+
+impl FilterKind {
+    pub const ALL: [FilterKind; 5] = [FilterKind::Box, FilterKind::Tent, FilterKind::Gaussian, FilterKind::Mitchell,
+        FilterKind::Lanczos];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::Mitchell => "mitchell",
+            FilterKind::Lanczos => "lanczos"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        FilterKind::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+
+    /// Radius used unless another one is given.
+    pub fn default_radius(self) -> Float {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell | FilterKind::Lanczos => 2.0
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, radius: Float) -> Filter {
+        Filter { kind: kind, radius: radius }
+    }
+
+    /// Number of pixels next to a pixel that samples taken in it can reach.
+    pub fn margin(&self) -> u32 {
+        (self.radius - 0.5).ceil().max(0.0) as u32
+    }
+
+    /// Weight of a sample that is (`dx`, `dy`) pixels away from a pixel center.
+    pub fn weight(&self, dx: Float, dy: Float) -> Float {
+        self.evaluate(dx) * self.evaluate(dy)
+    }
+
+    fn evaluate(&self, d: Float) -> Float {
+        let r = self.radius;
+        match self.kind {
+            // half open, so that samples on a pixel border count for exactly one pixel
+            FilterKind::Box => if d >= -r && d < r { 1.0 } else { 0.0 },
+            FilterKind::Tent => (1.0 - d.abs() / r).max(0.0),
+            FilterKind::Gaussian => {
+                let sigma = r / 3.0;
+                let gaussian = |d: Float| (-d * d / (2.0 * sigma * sigma)).exp();
+                (gaussian(d) - gaussian(r)).max(0.0)
+            },
+            FilterKind::Mitchell => mitchell(2.0 * d / r),
+            FilterKind::Lanczos if d.abs() < r => sinc(d) * sinc(d / r),
+            FilterKind::Lanczos => 0.0
+        }
+    }
+}
+
+/// The Mitchell-Netravali cubic with B = C = 1/3, which is zero for |x| >= 2.
+fn mitchell(x: Float) -> Float {
+    let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+    let x = x.abs();
+    if x >= 2.0 {
+        0.0
+    } else if x >= 1.0 {
+        ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+    }
+}
+
+fn sinc(x: Float) -> Float {
+    if x.abs() < 1e-5 { 1.0 } else { (PI * x).sin() / (PI * x) }
+}
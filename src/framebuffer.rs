@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use crate::math::*;
+use crate::adaptive::PixelVariance;
+use crate::filter::{Filter, FilterKind};
+use crate::aov::{Aov, AovBuffers, AovPixel};
+use crate::image::Image;
+use crate::checkpoint::{Decoder, Encoder};
+
+/// Everything that has been accumulated for a pixel so far, so that later passes can add more samples.
+#[derive(Copy, Clone, Default)]
+pub struct PixelState {
+    pub sum: Vec3,
+    pub variance: PixelVariance,
+    pub aovs: AovPixel,
+    /// Set once adaptive sampling considers the pixel converged.
+    pub converged: bool
+}
+
+/// A rectangle of pixels that is rendered as a unit.
+#[derive(Copy, Clone, Debug)]
+pub struct RenderTile {
+    pub left: u32,
+    pub width: u32,
+    pub top: u32,
+    pub height: u32
+}
+
+/// Filter weighted sum of samples and the sum of their weights, as red, green, blue and weight. The sums are kept in
+/// fixed point, which makes them exact: they do not depend on the order in which samples, passes and tiles are
+/// added up, so neither the tile size nor the number of passes changes a single bit of the image.
+#[derive(Copy, Clone, Default)]
+struct Splat([i128; 4]);
+
+/// Resolution of the fixed point sums of `Splat`.
+const SPLAT_SCALE: f64 = (1u64 << 32) as f64;
+
+/// Filter weighted sums of the samples of a tile, for the tile and the pixels around it that the reconstruction
+/// filter reaches.
+#[derive(Clone)]
+pub struct SplatBuffer {
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+    splats: Vec<Splat>
+}
+
+/// The per-pixel state of a render, from which the output images are built.
+#[derive(Clone)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    filter: Filter,
+    pixels: Vec<PixelState>,
+    /// The splats of every tile rendered so far, by tile position. They are only added up when the image is
+    /// resolved.
+    splats: HashMap<(u32, u32), SplatBuffer>
+}
+
+impl PixelState {
+    pub fn new() -> PixelState {
+        PixelState { sum: Vec3::zero(), variance: PixelVariance::new(), aovs: AovPixel::new(), converged: false }
+    }
+
+    pub fn samples(&self) -> usize {
+        self.variance.count()
+    }
+
+    pub fn mean(&self) -> Vec3 {
+        if self.samples() > 0 { (1.0 / self.samples() as Float) * self.sum } else { Vec3::zero() }
+    }
+
+    pub(crate) fn encode(&self, out: &mut Encoder) {
+        out.vec3(self.sum);
+        self.variance.encode(out);
+        self.aovs.encode(out);
+        out.bool(self.converged);
+    }
+
+    pub(crate) fn decode(input: &mut Decoder) -> Result<PixelState, String> {
+        Ok(PixelState { sum: input.vec3()?, variance: PixelVariance::decode(input)?, aovs: AovPixel::decode(input)?,
+            converged: input.bool()? })
+    }
+}
+
+impl Splat {
+    fn add(&mut self, weight: Float, color: Vec3) {
+        for (sum, value) in self.0.iter_mut().zip([color.r(), color.g(), color.b(), 1.0]) {
+            *sum += (weight as f64 * value as f64 * SPLAT_SCALE) as i128;
+        }
+    }
+
+    fn add_splat(&mut self, other: &Splat) {
+        for (sum, value) in self.0.iter_mut().zip(other.0) {
+            *sum += value;
+        }
+    }
+
+    fn weight(&self) -> Float {
+        (self.0[3] as f64 / SPLAT_SCALE) as Float
+    }
+
+    /// The weighted mean of the samples.
+    fn color(&self) -> Vec3 {
+        let weight = self.0[3] as f64;
+        let component = |sum: i128| (sum as f64 / weight) as Float;
+        Vec3::new(component(self.0[0]), component(self.0[1]), component(self.0[2]))
+    }
+}
+
+impl SplatBuffer {
+    /// Creates an empty buffer for `tile` and `margin` pixels around it, within an image of `width` x `height`.
+    pub fn new(tile: &RenderTile, margin: u32, width: u32, height: u32) -> SplatBuffer {
+        let left = tile.left.saturating_sub(margin);
+        let top = tile.top.saturating_sub(margin);
+        let right = (tile.left + tile.width + margin).min(width);
+        let bottom = (tile.top + tile.height + margin).min(height);
+        let size = ((right - left) * (bottom - top)) as usize;
+        SplatBuffer { left: left, top: top, width: right - left, height: bottom - top,
+            splats: vec![Splat::default(); size] }
+    }
+
+    /// Left, top, width and height of the pixels the buffer covers.
+    pub(crate) fn bounds(&self) -> (u32, u32, u32, u32) {
+        (self.left, self.top, self.width, self.height)
+    }
+
+    pub(crate) fn encode(&self, out: &mut Encoder) {
+        for value in [self.left, self.top, self.width, self.height] {
+            out.u32(value);
+        }
+        for splat in &self.splats {
+            for sum in splat.0 {
+                out.i128(sum);
+            }
+        }
+    }
+
+    pub(crate) fn decode(input: &mut Decoder) -> Result<SplatBuffer, String> {
+        let (left, top, buffer_width, buffer_height) = (input.u32()?, input.u32()?, input.u32()?, input.u32()?);
+        let mut splats = Vec::new();
+        for _ in 0..buffer_width as u64 * buffer_height as u64 {
+            splats.push(Splat([input.i128()?, input.i128()?, input.i128()?, input.i128()?]));
+        }
+        Ok(SplatBuffer { left: left, top: top, width: buffer_width, height: buffer_height, splats: splats })
+    }
+
+    /// Adds `color`, sampled at image position (`x`, `y`), to every pixel whose center is within the radius of
+    /// `filter`.
+    pub fn add(&mut self, x: Float, y: Float, color: Vec3, filter: &Filter) {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let first_x = ((x - filter.radius).ceil().max(self.left as Float)) as u32;
+        let first_y = ((y - filter.radius).ceil().max(self.top as Float)) as u32;
+        let last_x = ((x + filter.radius).floor() as i64).min((self.left + self.width) as i64 - 1);
+        let last_y = ((y + filter.radius).floor() as i64).min((self.top + self.height) as i64 - 1);
+        for py in first_y as i64..=last_y {
+            for px in first_x as i64..=last_x {
+                let weight = filter.weight(x - px as Float, y - py as Float);
+                if weight != 0.0 {
+                    let index = ((py as u32 - self.top) * self.width + px as u32 - self.left) as usize;
+                    self.splats[index].add(weight, color);
+                }
+            }
+        }
+    }
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Framebuffer {
+        Framebuffer { width: width, height: height, filter: filter,
+            pixels: vec![PixelState::new(); (width * height) as usize], splats: HashMap::new() }
+    }
+
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> &PixelState {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Returns a copy of the pixels in `tile`, row by row.
+    pub fn read(&self, tile: &RenderTile) -> Vec<PixelState> {
+        let mut pixels = Vec::with_capacity((tile.width * tile.height) as usize);
+        for y in tile.top..tile.top + tile.height {
+            let begin = (y * self.width + tile.left) as usize;
+            pixels.extend_from_slice(&self.pixels[begin..begin + tile.width as usize]);
+        }
+        pixels
+    }
+
+    /// Returns a copy of the splats of `tile`, which are empty if it has not been rendered yet.
+    pub fn read_splats(&self, tile: &RenderTile) -> SplatBuffer {
+        match self.splats.get(&(tile.top, tile.left)) {
+            Some(splats) => splats.clone(),
+            None => SplatBuffer::new(tile, self.filter.margin(), self.width, self.height)
+        }
+    }
+
+    /// Replaces the pixels in `tile` with `pixels`, given row by row, and its splats with `splats`.
+    pub fn write(&mut self, tile: &RenderTile, pixels: &[PixelState], splats: SplatBuffer) {
+        self.splats.insert((tile.top, tile.left), splats);
+        for (row, y) in (tile.top..tile.top + tile.height).enumerate() {
+            let begin = (y * self.width + tile.left) as usize;
+            let source = row * tile.width as usize;
+            self.pixels[begin..begin + tile.width as usize].copy_from_slice(&pixels[source..source + tile.width as usize]);
+        }
+    }
+
+    /// Writes everything accumulated so far, for `checkpoint`.
+    pub(crate) fn encode(&self, out: &mut Encoder) {
+        out.u32(self.width);
+        out.u32(self.height);
+        out.string(self.filter.kind.name());
+        out.float(self.filter.radius);
+        for pixel in &self.pixels {
+            pixel.encode(out);
+        }
+        // sorted, so that the same state always gives the same file
+        let mut keys: Vec<_> = self.splats.keys().copied().collect();
+        keys.sort_unstable();
+        out.u64(keys.len() as u64);
+        for key in keys {
+            out.u32(key.0);
+            out.u32(key.1);
+            self.splats[&key].encode(out);
+        }
+    }
+
+    pub(crate) fn decode(input: &mut Decoder) -> Result<Framebuffer, String> {
+        let (width, height) = (input.u32()?, input.u32()?);
+        let kind = input.string()?;
+        let kind = FilterKind::from_name(&kind).ok_or_else(|| format!("unknown filter {}", kind))?;
+        let filter = Filter::new(kind, input.float()?);
+        let mut pixels = Vec::new();
+        for _ in 0..width as u64 * height as u64 {
+            pixels.push(PixelState::decode(input)?);
+        }
+        let buffer_count = input.count(24)?;
+        let mut splats = HashMap::with_capacity(buffer_count);
+        for _ in 0..buffer_count {
+            let key = (input.u32()?, input.u32()?);
+            let buffer = SplatBuffer::decode(input)?;
+            let (left, top, buffer_width, buffer_height) = buffer.bounds();
+            if left as u64 + buffer_width as u64 > width as u64 || top as u64 + buffer_height as u64 > height as u64 {
+                return Err(String::from("splat buffer outside of the image"));
+            }
+            splats.insert(key, buffer);
+        }
+        Ok(Framebuffer { width: width, height: height, filter: filter, pixels: pixels, splats: splats })
+    }
+
+    fn image(&self, value: impl Fn(&PixelState) -> Vec3) -> Image {
+        Image::from_pixels(self.width, self.height, self.pixels.iter().map(value).collect())
+    }
+
+    /// The filtered radiance of every pixel. Pixels whose filter weights add up to nothing, which only filters with
+    /// negative lobes can cause, get the mean of their own samples.
+    pub fn color(&self) -> Image {
+        let mut sums = vec![Splat::default(); (self.width * self.height) as usize];
+        for buffer in self.splats.values() {
+            for y in 0..buffer.height {
+                for x in 0..buffer.width {
+                    let target = ((y + buffer.top) * self.width + x + buffer.left) as usize;
+                    sums[target].add_splat(&buffer.splats[(y * buffer.width + x) as usize]);
+                }
+            }
+        }
+        let colors = sums.iter().zip(self.pixels.iter()).map(|(sum, pixel)| {
+            if sum.weight() > EPSILON { sum.color() } else { pixel.mean() }
+        }).collect();
+        Image::from_pixels(self.width, self.height, colors)
+    }
+
+    /// The number of samples taken in every pixel, stored in all three channels.
+    pub fn samples(&self) -> Image {
+        self.image(|pixel| {
+            let samples = pixel.samples() as Float;
+            Vec3::new(samples, samples, samples)
+        })
+    }
+
+    pub fn aovs(&self, aovs: &[Aov]) -> AovBuffers {
+        let mut buffers = AovBuffers::new(aovs, self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                buffers.set(x, y, &self.get(x, y).aovs);
+            }
+        }
+        buffers
+    }
+
+    pub fn total_samples(&self) -> usize {
+        self.pixels.iter().map(PixelState::samples).sum()
+    }
+
+    /// Returns true if every pixel has `samples` samples or was stopped by adaptive sampling.
+    pub fn finished(&self, samples: usize) -> bool {
+        self.pixels.iter().all(|pixel| pixel.converged || pixel.samples() >= samples)
+    }
+
+    /// Mean relative standard error over all pixels; see `PixelVariance::error`.
+    pub fn noise(&self) -> Float {
+        self.pixels.iter().map(|pixel| pixel.variance.error()).sum::<Float>() / self.pixels.len() as Float
+    }
+}
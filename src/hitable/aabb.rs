@@ -12,21 +12,58 @@ impl AABB {
         AABB { min: min, max: max }
     }
 
+    /// Returns an inverted box that acts as the identity for `union`.
+    pub fn empty() -> AABB {
+        AABB { min: Vec3::new(MAX_FLOAT, MAX_FLOAT, MAX_FLOAT), max: Vec3::new(MIN_FLOAT, MIN_FLOAT, MIN_FLOAT) }
+    }
+
+    pub fn union(a: AABB, b: AABB) -> AABB {
+        let mut result = a;
+        for dim in 0..3 {
+            result.min[dim] = if b.min[dim] < result.min[dim] { b.min[dim] } else { result.min[dim] };
+            result.max[dim] = if b.max[dim] > result.max[dim] { b.max[dim] } else { result.max[dim] };
+        }
+        result
+    }
+
+    pub fn include(self, p: Vec3) -> AABB {
+        AABB::union(self, AABB::new(p, p))
+    }
+
+    pub fn center(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    /// Returns one of the eight corners; bit 0, 1 and 2 of `index` select the maximum in x, y and z.
+    pub fn corner(&self, index: usize) -> Vec3 {
+        Vec3::new(
+            if index & 1 == 0 { self.min.x() } else { self.max.x() },
+            if index & 2 == 0 { self.min.y() } else { self.max.y() },
+            if index & 4 == 0 { self.min.z() } else { self.max.z() })
+    }
+
+    pub fn surface_area(&self) -> Float {
+        let extent = self.max - self.min;
+        2.0 * (extent.x() * extent.y() + extent.y() * extent.z() + extent.z() * extent.x())
+    }
+
     pub fn hit(self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        let direction = ray.direction();
+        let inv_direction = Vec3::new(1.0 / direction.x(), 1.0 / direction.y(), 1.0 / direction.z());
+        self.hit_inverse(ray.origin(), inv_direction, t_min, t_max)
+    }
+
+    /// Slab test for a ray given by its origin and the reciprocal of its direction, which can be shared
+    /// between the boxes tested against the same ray.
+    pub fn hit_inverse(&self, origin: Vec3, inv_direction: Vec3, t_min: Float, t_max: Float) -> bool {
+        let (mut t_near, mut t_far) = (t_min, t_max);
         for dim in 0..3 {
-            let inv_d = 1.0 / ray.direction()[dim];
-            let mut t0 = (self.min[dim] - ray.origin()[dim]) * inv_d;
-            let mut t1 = (self.max[dim] - ray.origin()[dim]) * inv_d;
-            if inv_d < 0.0 {
-                std::mem::swap(&mut t0, &mut t1);
-            }
-            let t_mi = if t0 > t_min { t0 } else { t_min };
-            let t_ma = if t1 < t_max { t1 } else { t_max };
-            if t_ma <= t_mi {
-                return false;
-            }
+            let t0 = (self.min[dim] - origin[dim]) * inv_direction[dim];
+            let t1 = (self.max[dim] - origin[dim]) * inv_direction[dim];
+            t_near = t_near.max(t0.min(t1));
+            t_far = t_far.min(t0.max(t1));
         }
-        true
+        t_near <= t_far
     }
 }
 
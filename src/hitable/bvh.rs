@@ -0,0 +1,455 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::math::*;
+use crate::hitable::{Hitable, HitRecord, AABB};
+use crate::sampler::scene_random;
+
+/// Cost of traversing a node relative to intersecting a primitive, used by the surface area heuristic.
+const TRAVERSAL_COST: Float = 0.5;
+const INTERSECTION_COST: Float = 1.0;
+const BIN_COUNT: usize = 16;
+
+/// Deepest BVH that `BvhSettings::max_depth` can ask for.
+pub const MAX_DEPTH: usize = 64;
+/// Leaves are split regardless of the maximum depth if they would contain more primitives than this, so at most
+/// 16 more levels are needed for 2^32 primitives.
+const MAX_LEAF_PRIMITIVES: usize = u16::MAX as usize;
+const STACK_SIZE: usize = MAX_DEPTH + 32;
+
+/// Node of a `FlatBVH`, 32 bytes so that two of them fit into a cache line. Interior nodes have `count == 0`;
+/// their first child directly follows them in the node array and `offset` is the index of the second child,
+/// which lies on the positive side of `axis`. Leaves cover `count` primitives starting at `offset`.
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct FlatNode {
+    bounds: AABB,
+    offset: u32,
+    count: u16,
+    axis: u8,
+    padding: u8
+}
+
+const _: () = assert!(std::mem::size_of::<FlatNode>() == 32);
+
+/// The nodes of a BVH in depth-first order, over primitives that are only known by their bounds. Leaves cover
+/// ranges of the primitives in the order returned by `FlatNodes::build`, in which their owner stores them.
+pub(crate) struct FlatNodes {
+    nodes: Vec<FlatNode>
+}
+
+/// A BVH stored as an array of nodes in depth-first order, with the primitives of each leaf stored contiguously.
+pub struct FlatBVH {
+    tree: FlatNodes,
+    primitives: Vec<Arc<dyn Hitable>>
+}
+
+/// Recursive BVH whose children are separately allocated; kept to compare against `FlatBVH`.
+#[derive(Clone)]
+pub struct BVHNode {
+    left: Arc<dyn Hitable>,
+    right: Arc<dyn Hitable>,
+    bounding_box: AABB
+}
+
+/// How the elements of a node are divided between its children.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BvhSplit {
+    /// Sorts the elements along a random axis and splits at the middle element, down to single elements.
+    Middle,
+    /// Chooses the split with the lowest cost according to the binned surface area heuristic.
+    Sah
+}
+
+/// How the nodes of the BVH are stored and traversed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BvhLayout {
+    /// `FlatBVH`: a node array traversed front to back with a stack.
+    Flat,
+    /// `BVHNode`: a tree of separately allocated nodes that always visits both children.
+    Tree
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BvhSettings {
+    pub split: BvhSplit,
+    pub layout: BvhLayout,
+    /// Nodes with at most this many elements become leaves if splitting them is not worth it. Only used by `Sah`.
+    pub max_leaf_size: usize,
+    /// Nodes at this depth become leaves regardless of their size; the root is at depth 1.
+    pub max_depth: usize
+}
+
+/// Statistics about a built BVH. `sah_cost` is the expected cost of a ray that hits the root bounding box,
+/// in units of primitive intersections.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BvhStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub depth: usize,
+    pub sah_cost: Float
+}
+
+struct BuildItem {
+    index: usize,
+    bounds: AABB,
+    centroid: Vec3
+}
+
+impl Default for BvhSettings {
+    fn default() -> BvhSettings {
+        BvhSettings { split: BvhSplit::Sah, layout: BvhLayout::Flat, max_leaf_size: 4, max_depth: MAX_DEPTH }
+    }
+}
+
+impl BvhSplit {
+    pub const ALL: [BvhSplit; 2] = [BvhSplit::Middle, BvhSplit::Sah];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BvhSplit::Middle => "middle",
+            BvhSplit::Sah => "sah"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<BvhSplit> {
+        BvhSplit::ALL.iter().copied().find(|split| split.name() == name)
+    }
+}
+
+impl fmt::Display for BvhSplit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl BvhLayout {
+    pub const ALL: [BvhLayout; 2] = [BvhLayout::Flat, BvhLayout::Tree];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BvhLayout::Flat => "flat",
+            BvhLayout::Tree => "tree"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<BvhLayout> {
+        BvhLayout::ALL.iter().copied().find(|layout| layout.name() == name)
+    }
+}
+
+impl fmt::Display for BvhLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl BvhStats {
+    fn interior(&mut self, bounds: AABB, depth: usize) {
+        self.nodes += 1;
+        self.depth = self.depth.max(depth);
+        self.sah_cost += TRAVERSAL_COST * bounds.surface_area();
+    }
+
+    fn leaf(&mut self, bounds: AABB, count: usize, depth: usize) {
+        self.leaves += 1;
+        self.depth = self.depth.max(depth);
+        self.sah_cost += INTERSECTION_COST * count as Float * bounds.surface_area();
+    }
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} nodes, {} leaves, depth {}, SAH cost {:.2}", self.nodes, self.leaves, self.depth, self.sah_cost)
+    }
+}
+
+fn item_bounds(items: &[BuildItem]) -> AABB {
+    items.iter().fold(AABB::empty(), |bounds, item| AABB::union(bounds, item.bounds))
+}
+
+/// Builds a BVH over `elements` and stores it in the layout selected by `settings`; see `FlatBVH::build`.
+pub fn build_bvh(elements: &[Arc<dyn Hitable>], t0: Float, t1: Float, settings: &BvhSettings, seed: u64)
+        -> (Arc<dyn Hitable>, BvhStats) {
+    let (bvh, stats) = FlatBVH::build(elements, t0, t1, settings, seed);
+    match settings.layout {
+        BvhLayout::Flat => (Arc::new(bvh), stats),
+        BvhLayout::Tree => (Arc::new(BVHNode::from_flat(&bvh)), stats)
+    }
+}
+
+impl FlatBVH {
+    /// Builds a BVH over `elements`. The result only depends on `elements`, their order and, for the random axes
+    /// of `BvhSplit::Middle`, on `seed`.
+    pub fn build(elements: &[Arc<dyn Hitable>], t0: Float, t1: Float, settings: &BvhSettings, seed: u64)
+            -> (FlatBVH, BvhStats) {
+        let bounds: Vec<AABB> = elements.iter().map(|element| match element.bounding_box(t0, t1) {
+            Some(bounds) => bounds,
+            None => panic!("elements of BVHs need to have a bounding box.")
+        }).collect();
+        let (tree, order, stats) = FlatNodes::build(&bounds, settings, seed);
+        let primitives = order.iter().map(|&index| Arc::clone(&elements[index])).collect();
+        (FlatBVH { tree: tree, primitives: primitives }, stats)
+    }
+}
+
+impl FlatNodes {
+    /// Builds the nodes over primitives with the given `bounds`. Returns them together with the order in which
+    /// the primitives have to be stored, as indices into `bounds`, and the statistics of the BVH.
+    pub fn build(bounds: &[AABB], settings: &BvhSettings, seed: u64) -> (FlatNodes, Vec<usize>, BvhStats) {
+        if bounds.is_empty() {
+            panic!("BVHs need at least one element.");
+        }
+        if settings.max_depth > MAX_DEPTH {
+            panic!("BVHs can be at most {} levels deep.", MAX_DEPTH);
+        }
+        let mut items: Vec<BuildItem> = bounds.iter().enumerate()
+            .map(|(index, &bounds)| BuildItem { index: index, bounds: bounds, centroid: bounds.center() })
+            .collect();
+        let mut tree = FlatNodes { nodes: Vec::new() };
+        let mut order = Vec::with_capacity(items.len());
+        let mut stats = BvhStats::default();
+        tree.build_node(&mut items, 1, settings, seed, &mut order, &mut stats);
+        assert!(stats.depth <= STACK_SIZE, "BVH of depth {} exceeds the traversal stack.", stats.depth);
+        let root_area = tree.bounds().surface_area();
+        stats.sah_cost = if root_area > 0.0 { stats.sah_cost / root_area } else { 0.0 };
+        (tree, order, stats)
+    }
+
+    pub fn bounds(&self) -> AABB {
+        self.nodes[0].bounds
+    }
+
+    /// Visits the nodes front to back along the ray, skipping those that are farther away than the closest hit so
+    /// far. `hit` intersects the primitive with the given index, up to the given distance.
+    pub fn hit(&self, ray: &Ray, t_min: Float, t_max: Float, mut hit: impl FnMut(usize, Float) -> Option<HitRecord>)
+            -> Option<HitRecord> {
+        let origin = ray.origin();
+        let inv_direction = Vec3::new(1.0 / ray.direction().x(), 1.0 / ray.direction().y(), 1.0 / ray.direction().z());
+        let direction_negative = [inv_direction.x() < 0.0, inv_direction.y() < 0.0, inv_direction.z() < 0.0];
+        let mut result: Option<HitRecord> = None;
+        let mut closest = t_max;
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_size = 0;
+        let mut node_index = 0;
+        loop {
+            let node = &self.nodes[node_index];
+            if node.bounds.hit_inverse(origin, inv_direction, t_min, closest) {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    for primitive in first..first + node.count as usize {
+                        if let Some(rec) = hit(primitive, closest) {
+                            closest = rec.t;
+                            result = Some(rec);
+                        }
+                    }
+                } else {
+                    // continue with the child on the near side of the split and visit the other one later
+                    let (near, far) = if direction_negative[node.axis as usize] {
+                        (node.offset as usize, node_index + 1)
+                    } else {
+                        (node_index + 1, node.offset as usize)
+                    };
+                    stack[stack_size] = far as u32;
+                    stack_size += 1;
+                    node_index = near;
+                    continue;
+                }
+            }
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            node_index = stack[stack_size] as usize;
+        }
+        result
+    }
+
+    /// Appends the subtree over `items` in depth-first order and returns the index of its root. The primitives of
+    /// its leaves are appended to `order`.
+    fn build_node(&mut self, items: &mut [BuildItem], depth: usize, settings: &BvhSettings, seed: u64,
+            order: &mut Vec<usize>, stats: &mut BvhStats) -> usize {
+        let bounds = item_bounds(items);
+        let index = self.nodes.len();
+        // leaves that don't fit into a node are split even if that exceeds the maximum depth
+        let must_split = items.len() > MAX_LEAF_PRIMITIVES;
+        let split = if items.len() > 1 && depth < settings.max_depth {
+            FlatNodes::split(items, bounds, settings, must_split, scene_random(seed, 0, index as u64))
+        } else if must_split {
+            // halving keeps the levels below the maximum depth within the bound of MAX_LEAF_PRIMITIVES
+            Some(FlatNodes::median_split(items))
+        } else {
+            None
+        };
+        match split {
+            Some((mid, axis)) => {
+                stats.interior(bounds, depth);
+                self.nodes.push(FlatNode { bounds: bounds, offset: 0, count: 0, axis: axis as u8, padding: 0 });
+                let (left, right) = items.split_at_mut(mid);
+                self.build_node(left, depth + 1, settings, seed, order, stats);
+                self.nodes[index].offset = self.build_node(right, depth + 1, settings, seed, order, stats) as u32;
+            },
+            None => {
+                stats.leaf(bounds, items.len(), depth);
+                let offset = order.len() as u32;
+                order.extend(items.iter().map(|item| item.index));
+                self.nodes.push(FlatNode { bounds: bounds, offset: offset, count: items.len() as u16, axis: 0, padding: 0 });
+            }
+        }
+        index
+    }
+
+    /// Reorders `items` so that the first `mid` of them go into the first child and returns `mid` and the split
+    /// axis, or `None` if the node should become a leaf. If `force` is set, the items are always split. `random`
+    /// chooses the axis of `BvhSplit::Middle`.
+    fn split(items: &mut [BuildItem], bounds: AABB, settings: &BvhSettings, force: bool, random: u64)
+            -> Option<(usize, usize)> {
+        match settings.split {
+            BvhSplit::Middle => {
+                let dim = (random % 3) as usize;
+                items.sort_by(|a, b| a.bounds.min[dim].total_cmp(&b.bounds.min[dim]));
+                Some((items.len() / 2, dim))
+            },
+            BvhSplit::Sah => {
+                let leaf_allowed = !force && items.len() <= settings.max_leaf_size;
+                let centroid_bounds = items.iter().fold(AABB::empty(), |b, item| b.include(item.centroid));
+                match FlatNodes::sah_split(items, bounds, &centroid_bounds) {
+                    Some((dim, bin, cost)) => {
+                        if leaf_allowed && cost >= INTERSECTION_COST * items.len() as Float {
+                            return None;
+                        }
+                        // stable partition, so the result doesn't depend on the sort implementation
+                        items.sort_by_key(|item| bin_index(item.centroid, &centroid_bounds, dim) >= bin);
+                        let mid = items.iter().take_while(|item| bin_index(item.centroid, &centroid_bounds, dim) < bin).count();
+                        Some((mid, dim))
+                    },
+                    // all centroids coincide, so no split separates them
+                    None if leaf_allowed => None,
+                    None => Some((items.len() / 2, 0))
+                }
+            }
+        }
+    }
+
+    /// Sorts `items` by their centroids along the axis in which those spread the most and splits them in half.
+    fn median_split(items: &mut [BuildItem]) -> (usize, usize) {
+        let centroid_bounds = items.iter().fold(AABB::empty(), |b, item| b.include(item.centroid));
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let dim = (0..3).fold(0, |dim, axis| if extent[axis] > extent[dim] { axis } else { dim });
+        items.sort_by(|a, b| a.centroid[dim].total_cmp(&b.centroid[dim]));
+        (items.len() / 2, dim)
+    }
+
+    /// Evaluates the SAH cost of splitting between each of the `BIN_COUNT` centroid bins along each axis.
+    /// Returns the axis, the first bin of the right child and the cost of the best split.
+    fn sah_split(items: &[BuildItem], bounds: AABB, centroid_bounds: &AABB) -> Option<(usize, usize, Float)> {
+        let area = bounds.surface_area().max(EPSILON);
+        let mut best: Option<(usize, usize, Float)> = None;
+        for dim in 0..3 {
+            if centroid_bounds.max[dim] - centroid_bounds.min[dim] <= 0.0 {
+                continue;
+            }
+            let mut counts = [0usize; BIN_COUNT];
+            let mut bin_bounds = [AABB::empty(); BIN_COUNT];
+            for item in items {
+                let bin = bin_index(item.centroid, centroid_bounds, dim);
+                counts[bin] += 1;
+                bin_bounds[bin] = AABB::union(bin_bounds[bin], item.bounds);
+            }
+            // right_costs[i] is the area times count of bins i..BIN_COUNT
+            let mut right_costs = [0.0; BIN_COUNT];
+            let (mut right_bounds, mut right_count) = (AABB::empty(), 0);
+            for bin in (1..BIN_COUNT).rev() {
+                right_bounds = AABB::union(right_bounds, bin_bounds[bin]);
+                right_count += counts[bin];
+                right_costs[bin] = if right_count > 0 { right_bounds.surface_area() * right_count as Float } else { 0.0 };
+            }
+            let (mut left_bounds, mut left_count) = (AABB::empty(), 0);
+            for bin in 1..BIN_COUNT {
+                left_bounds = AABB::union(left_bounds, bin_bounds[bin - 1]);
+                left_count += counts[bin - 1];
+                if left_count == 0 || left_count == items.len() {
+                    continue;
+                }
+                let cost = TRAVERSAL_COST +
+                    INTERSECTION_COST * (left_bounds.surface_area() * left_count as Float + right_costs[bin]) / area;
+                if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    best = Some((dim, bin, cost));
+                }
+            }
+        }
+        best
+    }
+}
+
+impl BVHNode {
+    /// Converts a flattened BVH into a tree of nodes, which is what scenes used before `FlatBVH`. If the
+    /// root is a leaf, both children refer to it.
+    pub fn from_flat(bvh: &FlatBVH) -> BVHNode {
+        let root = &bvh.tree.nodes[0];
+        if root.count > 0 {
+            let leaf = BVHNode::child(bvh, 0);
+            return BVHNode { left: Arc::clone(&leaf), right: leaf, bounding_box: root.bounds };
+        }
+        BVHNode { left: BVHNode::child(bvh, 1), right: BVHNode::child(bvh, root.offset as usize), bounding_box: root.bounds }
+    }
+
+    fn child(bvh: &FlatBVH, index: usize) -> Arc<dyn Hitable> {
+        let node = &bvh.tree.nodes[index];
+        match node.count {
+            0 => Arc::new(BVHNode {
+                left: BVHNode::child(bvh, index + 1),
+                right: BVHNode::child(bvh, node.offset as usize),
+                bounding_box: node.bounds
+            }),
+            1 => Arc::clone(&bvh.primitives[node.offset as usize]),
+            count => {
+                let first = node.offset as usize;
+                let leaf: Vec<Arc<dyn Hitable>> = bvh.primitives[first..first + count as usize].to_vec();
+                Arc::new(leaf)
+            }
+        }
+    }
+}
+
+fn bin_index(centroid: Vec3, centroid_bounds: &AABB, dim: usize) -> usize {
+    let extent = centroid_bounds.max[dim] - centroid_bounds.min[dim];
+    let bin = ((centroid[dim] - centroid_bounds.min[dim]) / extent * BIN_COUNT as Float) as usize;
+    bin.min(BIN_COUNT - 1)
+}
+
+impl Hitable for FlatBVH {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        self.tree.hit(ray, t_min, t_max, |primitive, closest| self.primitives[primitive].hit(ray, t_min, closest))
+    }
+
+    fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
+        Some(self.tree.bounds())
+    }
+}
+
+impl Hitable for BVHNode {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        if self.bounding_box.hit(ray, t_min, t_max) {
+            let left_rec = self.left.hit(ray, t_min, t_max);
+            let right_rec = self.right.hit(ray, t_min, t_max);
+            if let (Some(left), Some(right)) = (left_rec, right_rec) {
+                if left.t < right.t {
+                    Some(left)
+                } else {
+                    Some(right)
+                }
+            } else if left_rec.is_some() {
+                left_rec
+            } else {
+                right_rec
+            }
+        } else {
+            None
+        }
+    }
+
+    fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
+        Some(self.bounding_box)
+    }
+}
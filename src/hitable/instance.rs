@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use crate::math::*;
+use crate::hitable::{Hitable, HitRecord, AABB};
+
+/// Places a shared object in the scene with an affine transform. Rays are transformed into object space
+/// instead of transforming the geometry, so any number of instances can share the same object.
+pub struct Instance {
+    object: Arc<dyn Hitable>,
+    /// Object to world space transform.
+    transform: Transform
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hitable>, transform: Transform) -> Instance {
+        Instance { object: object, transform: transform }
+    }
+}
+
+impl Hitable for Instance {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        transformed_hit(self.object.as_ref(), &self.transform, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
+        let bounds = self.object.bounding_box(t0, t1)?;
+        Some((0..8).fold(AABB::empty(), |result, corner| result.include(self.transform.point(bounds.corner(corner)))))
+    }
+}
+
+/// Intersects `object`, placed in the scene by the object to world space `transform`, with a world space ray.
+pub fn transformed_hit(object: &dyn Hitable, transform: &Transform, ray: &Ray, t_min: Float, t_max: Float)
+        -> Option<HitRecord> {
+    // the direction is not normalized, so ray parameters are the same in both spaces
+    let to_object = transform.inverse();
+    let object_ray = Ray::new(to_object.point(ray.origin()), to_object.vector(ray.direction()), ray.time());
+    let rec = object.hit(&object_ray, t_min, t_max)?;
+    Some(HitRecord {
+        p: ray.eval(rec.t),
+        normal: Vec3::normalize(transform.normal(rec.normal)),
+        ..rec
+    })
+}
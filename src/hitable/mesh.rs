@@ -0,0 +1,79 @@
+use crate::math::*;
+use crate::material::*;
+use crate::hitable::{Hitable, HitRecord, AABB, intersect_triangle};
+use crate::hitable::bvh::{BvhSettings, FlatNodes};
+
+/// Flat bounding boxes can not be hit by `AABB::hit`, so the bounds of the triangles are padded by this amount.
+const MIN_EXTENT: Float = 1e-4;
+
+/// A triangle mesh with shared vertex buffers and a BVH over its triangles, exposed as a single `Hitable`.
+pub struct TriangleMesh {
+    positions: Vec<Vec3>,
+    /// Per-vertex shading normals; empty if the mesh uses geometric normals.
+    normals: Vec<Vec3>,
+    /// Per-vertex texture coordinates; empty if the mesh has none.
+    uvs: Vec<[Float; 2]>,
+    /// Vertex indices of the triangles, in the order of the leaves of `bvh`.
+    indices: Vec<[u32; 3]>,
+    material: MaterialId,
+    bvh: FlatNodes
+}
+
+fn triangle_bounds(positions: &[Vec3], tri: &[u32; 3]) -> AABB {
+    let mut bounds = tri.iter().fold(AABB::empty(), |bounds, &i| bounds.include(positions[i as usize]));
+    for dim in 0..3 {
+        if bounds.max[dim] - bounds.min[dim] < MIN_EXTENT {
+            bounds.min[dim] -= MIN_EXTENT;
+            bounds.max[dim] += MIN_EXTENT;
+        }
+    }
+    bounds
+}
+
+impl TriangleMesh {
+    /// Creates the mesh and builds its BVH with `bvh`; `seed` is used as in `FlatBVH::build`.
+    pub fn new(positions: Vec<Vec3>, normals: Vec<Vec3>, uvs: Vec<[Float; 2]>, indices: Vec<[u32; 3]>,
+            material: MaterialId, bvh: &BvhSettings, seed: u64) -> TriangleMesh {
+        if indices.is_empty() {
+            panic!("triangle meshes need at least one triangle.");
+        }
+        if indices.iter().flatten().any(|&index| index as usize >= positions.len()) {
+            panic!("triangle mesh index out of range.");
+        }
+        if (!normals.is_empty() && normals.len() != positions.len()) || (!uvs.is_empty() && uvs.len() != positions.len()) {
+            panic!("triangle mesh vertex buffers need to have the same length.");
+        }
+        let bounds: Vec<AABB> = indices.iter().map(|tri| triangle_bounds(&positions, tri)).collect();
+        let (tree, order, _) = FlatNodes::build(&bounds, bvh, seed);
+        let indices = order.iter().map(|&index| indices[index]).collect();
+        TriangleMesh { positions: positions, normals: normals, uvs: uvs, indices: indices, material: material, bvh: tree }
+    }
+
+    fn hit_triangle(&self, index: usize, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        let [i0, i1, i2] = self.indices[index].map(|i| i as usize);
+        let (p0, p1, p2) = (self.positions[i0], self.positions[i1], self.positions[i2]);
+        let (t, u, v) = intersect_triangle(ray, p0, p1, p2, t_min, t_max)?;
+        let normal = if self.normals.is_empty() {
+            Vec3::normalize(Vec3::cross(p1 - p0, p2 - p0))
+        } else {
+            Vec3::normalize((1.0 - u - v) * self.normals[i0] + u * self.normals[i1] + v * self.normals[i2])
+        };
+        let (tex_u, tex_v) = if self.uvs.is_empty() {
+            (u, v)
+        } else {
+            let (uv0, uv1, uv2) = (self.uvs[i0], self.uvs[i1], self.uvs[i2]);
+            ((1.0 - u - v) * uv0[0] + u * uv1[0] + v * uv2[0], (1.0 - u - v) * uv0[1] + u * uv1[1] + v * uv2[1])
+        };
+        Some(HitRecord { t: t, p: ray.eval(t), normal: normal, u: tex_u, v: tex_v, material: self.material, object: 0 })
+    }
+}
+
+impl Hitable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        self.bvh.hit(ray, t_min, t_max, |index, closest| self.hit_triangle(index, ray, t_min, closest))
+    }
+
+    fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
+        Some(self.bvh.bounds())
+    }
+}
@@ -1,15 +1,33 @@
 mod aabb;
+mod bvh;
+mod instance;
+mod mesh;
+mod motion;
 
 use std::sync::Arc;
-use std::cmp::Ordering;
-use rand::prelude::*;
 use super::math::*;
 use super::material::*;
 pub use crate::hitable::aabb::AABB;
+pub use crate::hitable::bvh::{build_bvh, BvhLayout, BvhSettings, BvhSplit, BvhStats, MAX_DEPTH};
+pub use crate::hitable::instance::Instance;
+pub use crate::hitable::mesh::TriangleMesh;
+pub use crate::hitable::motion::{Keyframes, MovingInstance, MovingSphere, Pose};
 
 pub trait Hitable: Send + Sync {
     fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord>;
     fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB>;
+
+    /// Solid angle density with which `random` generates `direction` from `origin`. Only primitives that can be
+    /// used as area lights implement this.
+    fn pdf_value(&self, _origin: Vec3, _direction: Vec3) -> Float {
+        0.0
+    }
+
+    /// Generates a direction from `origin` towards a random point on the surface, using the two uniform random
+    /// numbers in `u`.
+    fn random(&self, _origin: Vec3, _u: [Float; 2]) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -17,21 +35,29 @@ pub struct HitRecord {
     pub t: Float,
     pub p: Vec3,
     pub normal: Vec3,
-    pub material: Materials
+    /// Surface parameterization at `p`, used for texture lookups.
+    pub u: Float,
+    pub v: Float,
+    pub material: MaterialId,
+    /// Id of the scene object that was hit, 0 if the shape is not tagged with one.
+    pub object: ObjectId
 }
 
+/// Identifies a shape or instance of the scene description in the object id AOV.
+pub type ObjectId = u32;
+
 #[derive(Clone)]
 pub struct Sphere {
     center: Vec3,
     radius: Float,
-    material: Materials
+    material: MaterialId
 }
 
 #[derive(Clone)]
 pub struct Plane {
     normal: Vec3,
     distance: Float,
-    material: Materials
+    material: MaterialId
 }
 
 #[derive(Clone)]
@@ -39,75 +65,71 @@ pub struct Triangle {
     p0: Vec3,
     p1: Vec3,
     p2: Vec3,
-    material: Materials
+    /// Optional per-vertex shading normals, interpolated across the triangle.
+    normals: Option<[Vec3; 3]>,
+    /// Optional per-vertex texture coordinates; the barycentric coordinates are used if they are missing.
+    uvs: Option<[[Float; 2]; 3]>,
+    material: MaterialId
 }
 
-#[derive(Clone)]
-pub struct BVHNode {
-    left: Arc<dyn Hitable>,
-    right: Arc<dyn Hitable>,
-    bounding_box: AABB
+/// Marks every hit of `shape` with an object id, for the object id AOV.
+pub struct Tagged {
+    shape: Arc<dyn Hitable>,
+    id: ObjectId
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: Float, material: Materials) -> Sphere {
+    pub fn new(center: Vec3, radius: Float, material: MaterialId) -> Sphere {
         Sphere { center: center, radius: radius, material: material }
     }
 }
 
 impl Plane {
-    pub fn new(normal: Vec3, distance: Float, material: Materials) -> Plane {
+    pub fn new(normal: Vec3, distance: Float, material: MaterialId) -> Plane {
         Plane { normal: Vec3::normalize(normal), distance: distance, material: material }
     }
 }
 
 impl Triangle {
-    pub fn new(p0: Vec3, p1: Vec3, p2:Vec3, material: Materials) -> Triangle {
-        Triangle { p0: p0, p1: p1, p2: p2, material: material }
+    pub fn new(p0: Vec3, p1: Vec3, p2:Vec3, material: MaterialId) -> Triangle {
+        Triangle { p0: p0, p1: p1, p2: p2, normals: None, uvs: None, material: material }
+    }
+
+    pub fn with_attributes(p0: Vec3, p1: Vec3, p2: Vec3, normals: Option<[Vec3; 3]>, uvs: Option<[[Float; 2]; 3]>,
+            material: MaterialId) -> Triangle {
+        Triangle { p0: p0, p1: p1, p2: p2, normals: normals, uvs: uvs, material: material }
     }
 }
 
-impl BVHNode {
-    pub fn new(elements: &[Arc<dyn Hitable>], t0: Float, t1: Float) -> BVHNode {
-        let dim = rand::thread_rng().gen_range(0..3);
-        let mut els: Vec<Arc<dyn Hitable>> = Vec::from(elements);
-        els.sort_unstable_by(|a, b| {
-            let a_bbox = a.bounding_box(t0, t1);
-            let b_bbox = b.bounding_box(t0, t1);
-            if let (Some(abb), Some(bbb)) = (a_bbox, b_bbox) {
-                if abb.min[dim] - bbb.min[dim] < 0.0 {
-                    Ordering::Less
+/// Möller-Trumbore ray/triangle intersection; returns the ray parameter and the barycentric coordinates
+/// of `p1` and `p2` at the hit point.
+pub fn intersect_triangle(ray: &Ray, p0: Vec3, p1: Vec3, p2: Vec3, t_min: Float, t_max: Float) -> Option<(Float, Float, Float)> {
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+    let h = Vec3::cross(ray.direction(), edge2);
+    let a = Vec3::dot(edge1, h);
+    if a.abs() <= EPSILON {
+        None
+    } else {
+        let f = 1.0 / a;
+        let s = ray.origin() - p0;
+        let u = f * Vec3::dot(s, h);
+        if u < 0.0 || u > 1.0 {
+            None
+        } else {
+            let q = Vec3::cross(s, edge1);
+            let v = f * Vec3::dot(ray.direction(), q);
+            if v < 0.0 || u + v > 1.0 {
+                None
+            } else {
+                let t = f * Vec3::dot(edge2, q);
+                if t > t_min && t < t_max {
+                    Some((t, u, v))
                 } else {
-                    Ordering::Greater
+                    None
                 }
-            } else {
-                panic!("elements of BVH nodes need to have a bounding box.");
             }
-        });
-
-        let (left, right): (Arc<dyn Hitable>, Arc<dyn Hitable>) = if els.len() == 1 {
-            (els[0].clone(), els[0].clone())
-        } else if els.len() == 2 {
-            (els[0].clone(), els[1].clone())
-        } else {
-            (Arc::new(BVHNode::new(&els[0..(els.len() / 2)], t0, t1)), Arc::new(BVHNode::new(&els[(els.len() / 2)..els.len()], t0, t1)))
-        };
-
-        let mut min = Vec3::new(MAX_FLOAT, MAX_FLOAT, MAX_FLOAT);
-        let mut max = Vec3::new(MIN_FLOAT, MIN_FLOAT, MIN_FLOAT);
-        let left_bbox = left.bounding_box(t0, t1);
-        let right_bbox = right.bounding_box(t0, t1);
-        if let (Some(left), Some(right)) = (left_bbox, right_bbox) {
-            for dim in 0..3 {
-                min[dim] = if left.min[dim] < min[dim] { left.min[dim] } else { min[dim] };
-                max[dim] = if left.max[dim] > max[dim] { left.max[dim] } else { max[dim] };
-                min[dim] = if right.min[dim] < min[dim] { right.min[dim] } else { min[dim] };
-                max[dim] = if right.max[dim] > max[dim] { right.max[dim] } else { max[dim] };
-            }
-        } else {
-            panic!("elements of BVH nodes need to have a bounding box.");
         }
-        BVHNode { left: left, right: right, bounding_box: AABB::new(min, max) }
     }
 }
 
@@ -118,7 +140,7 @@ impl Hitable for Vec<Arc<dyn Hitable>> {
         for hitable in self.iter() {
             if let Some(rec) = hitable.hit(ray, t_min, closest) {
                 closest = rec.t;
-                result = Some(HitRecord { t: rec.t, p: rec.p, normal: rec.normal, material: rec.material });
+                result = Some(rec);
             }
         }
         result
@@ -139,6 +161,38 @@ impl Hitable for Vec<Arc<dyn Hitable>> {
         }
         Some(AABB::new(min, max))
     }
+
+    /// Light lists pick one of their elements uniformly.
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> Float {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let sum: Float = self.iter().map(|hitable| hitable.pdf_value(origin, direction)).sum();
+        sum / self.len() as Float
+    }
+
+    fn random(&self, origin: Vec3, u: [Float; 2]) -> Vec3 {
+        // the first number picks the element; what is left of it is uniform again and is passed on
+        let scaled = u[0] * self.len() as Float;
+        let index = (scaled as usize).min(self.len() - 1);
+        self[index].random(origin, [(scaled - index as Float).min(1.0 - EPSILON / 2.0), u[1]])
+    }
+}
+
+impl Sphere {
+    /// Spherical mapping: `u` follows the longitude, `v` the latitude of `p` around the center.
+    fn surface_coordinates(&self, p: Vec3) -> (Float, Float) {
+        let d = Vec3::normalize(p - self.center);
+        let phi = d.z().atan2(d.x());
+        let theta = d.y().clamp(-1.0, 1.0).asin();
+        (1.0 - (phi + PI) / (2.0 * PI), (theta + PI / 2.0) / PI)
+    }
+
+    fn hit_record(&self, ray: &Ray, t: Float) -> HitRecord {
+        let p = ray.eval(t);
+        let (u, v) = self.surface_coordinates(p);
+        HitRecord { t: t, p: p, normal: (p - self.center) / self.radius, u: u, v: v, material: self.material, object: 0 }
+    }
 }
 
 impl Hitable for Sphere {
@@ -152,13 +206,11 @@ impl Hitable for Sphere {
             let sqrt = (b * b - a * c).sqrt();
             let temp1 = (-b - sqrt) / a;
             if temp1 < t_max && temp1 > t_min {
-                let p = ray.eval(temp1);
-                return Some(HitRecord { t: temp1, p: p, normal: (p - self.center) / self.radius, material: self.material });
+                return Some(self.hit_record(ray, temp1));
             }
             let temp2 = (-b + sqrt) / a;
             if temp2 < t_max && temp2 > t_min {
-                let p = ray.eval(temp2);
-                return Some(HitRecord { t: temp2, p: p, normal: (p - self.center) / self.radius, material: self.material });
+                return Some(self.hit_record(ray, temp2));
             }
         }
         None
@@ -169,6 +221,38 @@ impl Hitable for Sphere {
         let radius_vec = Vec3::new(abs_radius, abs_radius, abs_radius);
         Some(AABB::new(self.center - radius_vec, self.center + radius_vec))
     }
+
+    /// Directions are sampled uniformly from the cone the sphere subtends, or from all directions
+    /// if `origin` is inside of the sphere.
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> Float {
+        if self.hit(&Ray::new(origin, direction, 0.0), 0.001, MAX_FLOAT).is_none() {
+            return 0.0;
+        }
+        let distance_squared = (self.center - origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            1.0 / (4.0 * PI)
+        } else {
+            let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+            1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+        }
+    }
+
+    fn random(&self, origin: Vec3, u: [Float; 2]) -> Vec3 {
+        let direction = self.center - origin;
+        let distance_squared = direction.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return sample_unit_vector(u);
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let z = 1.0 + u[0] * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * u[1];
+        let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+        let w = Vec3::normalize(direction);
+        let (u, v) = Vec3::orthonormal_basis(w);
+        phi.cos() * sin_theta * u + phi.sin() * sin_theta * v + z * w
+    }
 }
 
 impl Hitable for Plane {
@@ -180,10 +264,16 @@ impl Hitable for Plane {
             let plane_to_origin = self.distance * self.normal - ray.origin();
             let t = Vec3::dot(plane_to_origin, self.normal) / denom;
             if t >= t_min && t <= t_max {
+                let p = ray.origin() + t * ray.direction();
+                // planar projection onto two axes orthogonal to the normal
+                let helper = if self.normal.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+                let tangent = Vec3::normalize(Vec3::cross(helper, self.normal));
+                let bitangent = Vec3::cross(self.normal, tangent);
                 Some(HitRecord {
-                    t: t, p: ray.origin() + t * ray.direction(),
+                    t: t, p: p,
                     normal: self.normal,
-                    material: self.material })
+                    u: Vec3::dot(p, tangent), v: Vec3::dot(p, bitangent),
+                    material: self.material, object: 0 })
             } else {
                 None
             }
@@ -197,36 +287,20 @@ impl Hitable for Plane {
 
 impl Hitable for Triangle {
     fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
-        let edge1 = self.p1 - self.p0;
-        let edge2 = self.p2 - self.p0;
-        let h = Vec3::cross(ray.direction(), edge2);
-        let a = Vec3::dot(edge1, h);
-        if a.abs() <= EPSILON {
-            None
-        } else {
-            let f = 1.0 / a;
-            let s = ray.origin() - self.p0;
-            let u = f * Vec3::dot(s, h);
-            if u < 0.0 || u > 1.0 {
-                None
-            } else {
-                let q = Vec3::cross(s, edge1);
-                let v = f * Vec3::dot(ray.direction(), q);
-                if v < 0.0 || u + v > 1.0 {
-                    None
-                } else {
-                    let t = f * Vec3::dot(edge2, q);
-                    if t > t_min && t < t_max {
-                        Some(HitRecord {
-                            t: t, p: ray.origin() + t * ray.direction(),
-                            normal: Vec3::normalize(Vec3::cross(edge1, edge2)),
-                            material: self.material })
-                    } else {
-                        None
-                    }
-                }
-            }
-        }
+        let (t, u, v) = intersect_triangle(ray, self.p0, self.p1, self.p2, t_min, t_max)?;
+        let normal = match self.normals {
+            Some([n0, n1, n2]) => Vec3::normalize((1.0 - u - v) * n0 + u * n1 + v * n2),
+            None => Vec3::normalize(Vec3::cross(self.p1 - self.p0, self.p2 - self.p0))
+        };
+        let (tex_u, tex_v) = match self.uvs {
+            Some([uv0, uv1, uv2]) => ((1.0 - u - v) * uv0[0] + u * uv1[0] + v * uv2[0], (1.0 - u - v) * uv0[1] + u * uv1[1] + v * uv2[1]),
+            None => (u, v)
+        };
+        Some(HitRecord {
+            t: t, p: ray.origin() + t * ray.direction(),
+            normal: normal,
+            u: tex_u, v: tex_v,
+            material: self.material, object: 0 })
     }
 
     fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
@@ -249,44 +323,50 @@ impl Hitable for Triangle {
         }
         Some(AABB::new(min, max))
     }
+
+    /// Points are sampled uniformly on the triangle's area.
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> Float {
+        match intersect_triangle(&Ray::new(origin, direction, 0.0), self.p0, self.p1, self.p2, 0.001, MAX_FLOAT) {
+            Some((t, _, _)) => {
+                let normal = Vec3::cross(self.p1 - self.p0, self.p2 - self.p0);
+                let area = 0.5 * normal.length();
+                let distance_squared = t * t * direction.length_squared();
+                let cosine = (Vec3::dot(direction, normal) / (direction.length() * normal.length())).abs();
+                if cosine <= EPSILON { 0.0 } else { distance_squared / (cosine * area) }
+            },
+            None => 0.0
+        }
+    }
+
+    fn random(&self, origin: Vec3, u: [Float; 2]) -> Vec3 {
+        let su = u[0].sqrt();
+        let r = u[1];
+        let p = self.p0 + su * (1.0 - r) * (self.p1 - self.p0) + su * r * (self.p2 - self.p0);
+        p - origin
+    }
 }
 
-impl Hitable for BVHNode {
+impl Tagged {
+    pub fn new(shape: Arc<dyn Hitable>, id: ObjectId) -> Tagged {
+        Tagged { shape: shape, id: id }
+    }
+}
+
+impl Hitable for Tagged {
     fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
-        if self.bounding_box.hit(&ray, t_min, t_max) {
-            let left_rec = self.left.hit(ray, t_min, t_max);
-            let right_rec = self.right.hit(ray, t_min, t_max);
-            if let (Some(left), Some(right)) = (left_rec, right_rec) {
-                if left.t < right.t {
-                    Some(HitRecord {
-                        t: left.t, p: left.p,
-                        normal: left.normal,
-                        material: left.material })
-                } else {
-                    Some(HitRecord {
-                        t: right.t, p: right.p,
-                        normal: right.normal,
-                        material: right.material })
-                }
-            } else if let Some(left) = left_rec {
-                Some(HitRecord {
-                    t: left.t, p: left.p,
-                    normal: left.normal,
-                    material: left.material })
-            } else if let Some(right) = right_rec {
-                Some(HitRecord {
-                    t: right.t, p: right.p,
-                    normal: right.normal,
-                    material: right.material })
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+        let rec = self.shape.hit(ray, t_min, t_max)?;
+        Some(HitRecord { object: self.id, ..rec })
     }
 
-    fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
-        Some(self.bounding_box)
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
+        self.shape.bounding_box(t0, t1)
     }
-}
\ No newline at end of file
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> Float {
+        self.shape.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Vec3, u: [Float; 2]) -> Vec3 {
+        self.shape.random(origin, u)
+    }
+}
@@ -0,0 +1,191 @@
+use std::sync::Arc;
+
+use crate::math::*;
+use crate::material::*;
+use crate::hitable::{Hitable, HitRecord, Sphere, AABB};
+use crate::hitable::instance::transformed_hit;
+
+/// Number of steps per keyframe interval at which the bounds of a moving instance are evaluated.
+const BOUNDS_STEPS: usize = 16;
+
+/// Values that can be blended between two keyframes.
+pub trait Interpolate: Copy {
+    fn lerp(a: Self, b: Self, t: Float) -> Self;
+}
+
+impl Interpolate for Vec3 {
+    fn lerp(a: Vec3, b: Vec3, t: Float) -> Vec3 {
+        (1.0 - t) * a + t * b
+    }
+}
+
+/// Scale, rotation and translation of an instance, applied in this order. `rotate` gives angles in degrees
+/// around the x, y and z axis. Poses are interpolated component-wise, so rotations of more than half a turn
+/// between two keyframes are possible.
+#[derive(Copy, Clone)]
+pub struct Pose {
+    pub scale: Vec3,
+    pub rotate: Vec3,
+    pub translate: Vec3
+}
+
+impl Pose {
+    pub fn new(scale: Vec3, rotate: Vec3, translate: Vec3) -> Pose {
+        Pose { scale: scale, rotate: rotate, translate: translate }
+    }
+
+    pub fn identity() -> Pose {
+        Pose::new(Vec3::one(), Vec3::zero(), Vec3::zero())
+    }
+
+    /// Panics if one of the scale factors is zero.
+    pub fn transform(&self) -> Transform {
+        Transform::scale(self.scale)
+            .then(&Transform::rotate(Vec3::new(1.0, 0.0, 0.0), self.rotate.x()))
+            .then(&Transform::rotate(Vec3::new(0.0, 1.0, 0.0), self.rotate.y()))
+            .then(&Transform::rotate(Vec3::new(0.0, 0.0, 1.0), self.rotate.z()))
+            .then(&Transform::translate(self.translate))
+    }
+}
+
+impl Interpolate for Pose {
+    fn lerp(a: Pose, b: Pose, t: Float) -> Pose {
+        Pose::new(Vec3::lerp(a.scale, b.scale, t), Vec3::lerp(a.rotate, b.rotate, t),
+            Vec3::lerp(a.translate, b.translate, t))
+    }
+}
+
+/// Piecewise linear animation of a value. The value is held constant before the first and after the last keyframe.
+#[derive(Clone)]
+pub struct Keyframes<T> {
+    keys: Vec<(Float, T)>
+}
+
+impl<T: Interpolate> Keyframes<T> {
+    pub fn new(time: Float, value: T) -> Keyframes<T> {
+        Keyframes { keys: vec!((time, value)) }
+    }
+
+    /// Panics if `time` is not after the time of the last keyframe.
+    pub fn add(&mut self, time: Float, value: T) {
+        if time <= self.last().0 {
+            panic!("keyframes need to be added in increasing order of time.");
+        }
+        self.keys.push((time, value));
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn last(&self) -> (Float, T) {
+        self.keys[self.keys.len() - 1]
+    }
+
+    pub fn at(&self, time: Float) -> T {
+        let next = self.keys.partition_point(|(key_time, _)| *key_time <= time);
+        if next == 0 {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len() {
+            return self.last().1;
+        }
+        let ((time0, value0), (time1, value1)) = (self.keys[next - 1], self.keys[next]);
+        T::lerp(value0, value1, (time - time0) / (time1 - time0))
+    }
+
+    /// Returns `t0` and `t1` clamped to the animated range, with the times of all keyframes in between. The value
+    /// changes linearly between consecutive returned times.
+    pub fn times(&self, t0: Float, t1: Float) -> Vec<Float> {
+        let (first, last) = (self.keys[0].0, self.last().0);
+        let (t0, t1) = (t0.clamp(first, last), t1.clamp(first, last));
+        let mut times = vec!(t0);
+        times.extend(self.keys.iter().map(|(time, _)| *time).filter(|time| *time > t0 && *time < t1));
+        if t1 > t0 {
+            times.push(t1);
+        }
+        times
+    }
+}
+
+/// A sphere whose center moves along a keyframed path.
+#[derive(Clone)]
+pub struct MovingSphere {
+    center: Keyframes<Vec3>,
+    radius: Float,
+    material: MaterialId
+}
+
+impl MovingSphere {
+    pub fn new(center: Keyframes<Vec3>, radius: Float, material: MaterialId) -> MovingSphere {
+        MovingSphere { center: center, radius: radius, material: material }
+    }
+
+    fn at(&self, time: Float) -> Sphere {
+        Sphere::new(self.center.at(time), self.radius, self.material)
+    }
+}
+
+impl Hitable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        self.at(ray.time()).hit(ray, t_min, t_max)
+    }
+
+    /// The center moves linearly between keyframes, so the boxes at the keyframes inside of the interval
+    /// and at its ends cover all positions.
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
+        let mut result = AABB::empty();
+        for time in self.center.times(t0, t1) {
+            result = AABB::union(result, self.at(time).bounding_box(time, time)?);
+        }
+        Some(result)
+    }
+}
+
+/// An instance whose pose is animated with keyframes.
+pub struct MovingInstance {
+    object: Arc<dyn Hitable>,
+    poses: Keyframes<Pose>
+}
+
+impl MovingInstance {
+    pub fn new(object: Arc<dyn Hitable>, poses: Keyframes<Pose>) -> MovingInstance {
+        MovingInstance { object: object, poses: poses }
+    }
+}
+
+impl Hitable for MovingInstance {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        transformed_hit(self.object.as_ref(), &self.poses.at(ray.time()).transform(), ray, t_min, t_max)
+    }
+
+    /// Rotations move points along curves, so the transformed bounds are evaluated in small steps. As long as a
+    /// step turns by less than half a revolution, a point stays closer to one of the step's end points than the
+    /// distance between them, which is added as a margin.
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
+        let bounds = self.object.bounding_box(t0, t1)?;
+        let times = self.poses.times(t0, t1);
+        let corners = |time| {
+            let transform = self.poses.at(time).transform();
+            [0, 1, 2, 3, 4, 5, 6, 7].map(|index| transform.point(bounds.corner(index)))
+        };
+        let mut previous = corners(times[0]);
+        let mut result = previous.iter().fold(AABB::empty(), |result, p| result.include(*p));
+        for interval in times.windows(2) {
+            for step in 1..=BOUNDS_STEPS {
+                let time = interval[0] + (interval[1] - interval[0]) * step as Float / BOUNDS_STEPS as Float;
+                let current = corners(time);
+                let mut margin: Float = 0.0;
+                let mut step_bounds = AABB::empty();
+                for (p0, p1) in previous.iter().zip(current.iter()) {
+                    margin = margin.max((*p1 - *p0).length());
+                    step_bounds = step_bounds.include(*p0).include(*p1);
+                }
+                let margin = Vec3::new(margin, margin, margin);
+                result = AABB::union(result, AABB::new(step_bounds.min - margin, step_bounds.max + margin));
+                previous = current;
+            }
+        }
+        Some(result)
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::math::*;
+
+mod exr;
+mod pfm;
+mod radiance;
+mod tonemap;
+pub use crate::image::exr::{Channel, ChannelKind};
+pub use crate::image::tonemap::{luminance, ToneMapOperator, ToneMapping};
+
+/// Floating point formats for storing the unclamped radiance of a render.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HdrFormat {
+    Pfm,
+    Radiance,
+    Exr
+}
+
+/// Channel type of OpenEXR output.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ExrPixel {
+    Half,
+    Float
+}
+
+/// A floating point RGB image, stored row by row starting at the top.
+#[derive(Clone)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<Vec3>
+}
+
+impl HdrFormat {
+    pub const ALL: [HdrFormat; 3] = [HdrFormat::Pfm, HdrFormat::Radiance, HdrFormat::Exr];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            HdrFormat::Pfm => "pfm",
+            HdrFormat::Radiance => "hdr",
+            HdrFormat::Exr => "exr"
+        }
+    }
+
+    /// Determines the format from the extension of `path`, ignoring case.
+    pub fn from_path(path: &Path) -> Option<HdrFormat> {
+        let extension = path.extension()?.to_str()?;
+        HdrFormat::ALL.iter().copied().find(|format| format.extension().eq_ignore_ascii_case(extension))
+    }
+}
+
+impl ExrPixel {
+    pub const ALL: [ExrPixel; 2] = [ExrPixel::Half, ExrPixel::Float];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ExrPixel::Half => "half",
+            ExrPixel::Float => "float"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ExrPixel> {
+        ExrPixel::ALL.iter().copied().find(|pixel| pixel.name() == name)
+    }
+}
+
+impl fmt::Display for ExrPixel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Image {
+        Image { width: width, height: height, pixels: vec![Vec3::zero(); (width * height) as usize] }
+    }
+
+    /// Creates an image from `pixels`, given row by row starting at the top.
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<Vec3>) -> Image {
+        if pixels.len() != (width * height) as usize {
+            panic!("{} pixels given for a {}x{} image", pixels.len(), width, height);
+        }
+        Image { width: width, height: height, pixels: pixels }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Vec3 {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Vec3) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    /// Returns the pixels of row `y`, from left to right.
+    pub fn row(&self, y: u32) -> &[Vec3] {
+        &self.pixels[(y * self.width) as usize..((y + 1) * self.width) as usize]
+    }
+
+    /// Writes the image as 8 bit sRGB, converted with `tone_mapping`.
+    pub fn write_png(&self, path: &Path, tone_mapping: &ToneMapping) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&tone_mapping.apply(self))?;
+        Ok(())
+    }
+
+    /// Writes the unclamped linear values of the image to `path`. `pixel` is only used for OpenEXR files.
+    pub fn write_hdr(&self, path: &Path, format: HdrFormat, pixel: ExrPixel) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        match format {
+            HdrFormat::Pfm => pfm::write(self, &mut out)?,
+            HdrFormat::Radiance => radiance::write(self, &mut out)?,
+            HdrFormat::Exr => exr::write(self, pixel, &mut out)?
+        }
+        out.flush()
+    }
+}
+
+/// Writes `channels`, which may come from several images of the same size, to a multi-layer OpenEXR file.
+pub fn write_exr(path: &Path, channels: &[Channel]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    exr::write_channels(channels, &mut out)?;
+    out.flush()
+}
@@ -0,0 +1,176 @@
+use std::io;
+use std::io::Write;
+
+use crate::math::*;
+use crate::image::{ExrPixel, Image};
+
+const MAGIC: u32 = 20000630;
+/// Version 2 of the file format, single-part scanline image.
+const VERSION: u32 = 2;
+
+/// Pixel type of a single OpenEXR channel.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ChannelKind {
+    Uint,
+    Half,
+    Float
+}
+
+/// A channel of an OpenEXR file, holding one component of every pixel of an image.
+pub struct Channel<'a> {
+    pub name: String,
+    pub image: &'a Image,
+    pub component: usize,
+    pub kind: ChannelKind
+}
+
+impl ChannelKind {
+    fn pixel_type(self) -> i32 {
+        match self {
+            ChannelKind::Uint => 0,
+            ChannelKind::Half => 1,
+            ChannelKind::Float => 2
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ChannelKind::Half => 2,
+            ChannelKind::Uint | ChannelKind::Float => 4
+        }
+    }
+}
+
+impl From<ExrPixel> for ChannelKind {
+    fn from(pixel: ExrPixel) -> ChannelKind {
+        match pixel {
+            ExrPixel::Half => ChannelKind::Half,
+            ExrPixel::Float => ChannelKind::Float
+        }
+    }
+}
+
+/// Writes an uncompressed single-part scanline OpenEXR image with R, G and B channels.
+pub fn write<W: Write>(image: &Image, pixel: ExrPixel, out: &mut W) -> io::Result<()> {
+    let channels: Vec<Channel> = ["R", "G", "B"].iter().enumerate().map(|(component, name)|
+        Channel { name: name.to_string(), image: image, component: component, kind: pixel.into() }).collect();
+    write_channels(&channels, out)
+}
+
+/// Writes an uncompressed single-part scanline OpenEXR image with the given channels, which all have to be taken
+/// from images of the same size. Layers are expressed by the usual `layer.channel` names.
+pub fn write_channels<W: Write>(channels: &[Channel], out: &mut W) -> io::Result<()> {
+    let image = match channels.first() {
+        Some(channel) => channel.image,
+        None => panic!("an OpenEXR image needs at least one channel")
+    };
+    if channels.iter().any(|channel| channel.image.width() != image.width() || channel.image.height() != image.height()) {
+        panic!("all channels of an OpenEXR image must have the same size");
+    }
+    let (width, height) = (image.width() as i32, image.height() as i32);
+    // channels have to be listed, and stored, in alphabetical order
+    let mut channels: Vec<&Channel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC.to_le_bytes());
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    let mut list = Vec::new();
+    for channel in &channels {
+        list.extend_from_slice(channel.name.as_bytes());
+        list.push(0);
+        list.extend_from_slice(&channel.kind.pixel_type().to_le_bytes());
+        // linear flag and three reserved bytes, followed by the x and y sampling rate
+        list.extend_from_slice(&[0, 0, 0, 0]);
+        list.extend_from_slice(&1i32.to_le_bytes());
+        list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    list.push(0);
+    attribute(&mut header, "channels", "chlist", &list);
+    // no compression
+    attribute(&mut header, "compression", "compression", &[0]);
+    let window = box2i(width, height);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    // increasing y
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+    out.write_all(&header)?;
+
+    // every uncompressed chunk holds one scanline, preceded by its y coordinate and the size of its data
+    let line_size = width as usize * channels.iter().map(|channel| channel.kind.size()).sum::<usize>();
+    let table_size = height as usize * 8;
+    for y in 0..height as usize {
+        let offset = header.len() + table_size + y * (8 + line_size);
+        out.write_all(&(offset as u64).to_le_bytes())?;
+    }
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..image.height() {
+        line.clear();
+        // within a scanline all values of the first channel come first, then the second channel and so on
+        for channel in &channels {
+            for value in channel.image.row(y).iter().map(|color| color[channel.component]) {
+                match channel.kind {
+                    ChannelKind::Uint => line.extend_from_slice(&(value.max(0.0) as u32).to_le_bytes()),
+                    ChannelKind::Half => line.extend_from_slice(&to_half(value).to_le_bytes()),
+                    ChannelKind::Float => line.extend_from_slice(&value.to_le_bytes())
+                }
+            }
+        }
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_size as i32).to_le_bytes())?;
+        out.write_all(&line)?;
+    }
+    Ok(())
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// An integer box covering the whole image, given by its minimum and maximum pixel coordinates.
+fn box2i(width: i32, height: i32) -> Vec<u8> {
+    [0, 0, width - 1, height - 1].iter().flat_map(|value: &i32| value.to_le_bytes()).collect()
+}
+
+/// Converts to IEEE 754 half precision, rounding to nearest even. Values too large for a half become infinite.
+fn to_half(value: Float) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        // infinity stays infinity, NaN keeps a set mantissa bit
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        // subnormal half or zero; the implicit leading bit becomes explicit
+        if half_exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let half = mantissa >> shift;
+        let remainder = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = remainder > halfway || (remainder == halfway && half & 1 == 1);
+        return sign | (half + round as u32) as u16;
+    }
+    let half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+    let remainder = mantissa & 0x1fff;
+    let round = remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1);
+    // a carry out of the mantissa correctly increments the exponent, up to infinity
+    sign | (half + round as u32) as u16
+}
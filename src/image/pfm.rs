@@ -0,0 +1,18 @@
+use std::io;
+use std::io::Write;
+
+use crate::image::Image;
+
+/// Writes a color Portable Float Map. The negative scale marks the data as little endian, and rows are stored
+/// from the bottom up.
+pub fn write<W: Write>(image: &Image, out: &mut W) -> io::Result<()> {
+    write!(out, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+    for y in (0..image.height()).rev() {
+        for pixel in image.row(y) {
+            for channel in 0..3 {
+                out.write_all(&pixel[channel].to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::io;
+use std::io::Write;
+
+use crate::math::*;
+use crate::image::Image;
+
+/// Shortest run of equal bytes that is stored as a run in run-length encoded scanlines.
+const MIN_RUN: usize = 4;
+
+/// Writes a Radiance RGBE (.hdr) image. Scanlines are run-length encoded if the width allows it; negative values
+/// can not be represented and are stored as zero.
+pub fn write<W: Write>(image: &Image, out: &mut W) -> io::Result<()> {
+    write!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", image.height(), image.width())?;
+    let encode = image.width() >= 8 && image.width() < 0x8000;
+    for y in 0..image.height() {
+        let rgbe: Vec<[u8; 4]> = image.row(y).iter().map(|pixel| to_rgbe(*pixel)).collect();
+        if encode {
+            let width = image.width();
+            out.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+            for component in 0..4 {
+                let values: Vec<u8> = rgbe.iter().map(|pixel| pixel[component]).collect();
+                write_rle(&values, out)?;
+            }
+        } else {
+            for pixel in &rgbe {
+                out.write_all(pixel)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stores the color as 8 bit mantissas with an exponent shared by all channels.
+fn to_rgbe(color: Vec3) -> [u8; 4] {
+    let color = Vec3::new(color.r().max(0.0), color.g().max(0.0), color.b().max(0.0));
+    let max = color.r().max(color.g()).max(color.b());
+    if max.is_nan() || max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // smallest exponent with max < 2^exponent
+    let mut exponent = max.log2().floor() as i32 + 1;
+    if max >= (exponent as Float).exp2() {
+        exponent += 1;
+    }
+    let scale = 256.0 * (-exponent as Float).exp2();
+    let mantissa = |value: Float| (value * scale).min(255.0) as u8;
+    [mantissa(color.r()), mantissa(color.g()), mantissa(color.b()), (exponent + 128).clamp(0, 255) as u8]
+}
+
+/// Run-length encodes one component of a scanline. Runs are stored as a count above 128 followed by the repeated
+/// byte, other bytes as a count of up to 128 followed by the bytes themselves.
+fn write_rle<W: Write>(values: &[u8], out: &mut W) -> io::Result<()> {
+    let mut current = 0;
+    while current < values.len() {
+        // find the next run that is long enough to be worth encoding
+        let (mut run_begin, mut run_length, mut previous_length) = (current, 0, 0);
+        while run_length < MIN_RUN && run_begin < values.len() {
+            run_begin += run_length;
+            previous_length = run_length;
+            run_length = 1;
+            while run_begin + run_length < values.len() && run_length < 127
+                    && values[run_begin] == values[run_begin + run_length] {
+                run_length += 1;
+            }
+        }
+        // a short run right before the long one is still cheaper as a run
+        if previous_length > 1 && previous_length == run_begin - current {
+            out.write_all(&[128 + previous_length as u8, values[current]])?;
+            current = run_begin;
+        }
+        while current < run_begin {
+            let count = (run_begin - current).min(128);
+            out.write_all(&[count as u8])?;
+            out.write_all(&values[current..current + count])?;
+            current += count;
+        }
+        if run_length >= MIN_RUN {
+            out.write_all(&[128 + run_length as u8, values[run_begin]])?;
+            current += run_length;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use std::fmt;
+
+use crate::math::*;
+use crate::image::Image;
+
+/// Operators that compress linear radiance into the displayable range.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    /// Clips every channel at 1.
+    Clamp,
+    /// Reinhard et al.'s global operator L / (1 + L) on the luminance.
+    Reinhard,
+    /// Reinhard's operator with a white point, the smallest luminance that is mapped to 1.
+    ReinhardExtended,
+    /// Narkowicz's curve fit of the ACES filmic reference rendering transform.
+    Aces,
+    /// John Hable's filmic curve from Uncharted 2.
+    Hable
+}
+
+/// Converts linear radiance to 8 bit sRGB: exposure, then tone mapping, then the sRGB transfer function.
+#[derive(Copy, Clone, Debug)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// Exposure compensation in stops; every stop doubles the brightness.
+    pub exposure: Float,
+    /// White point of the extended Reinhard and Hable operators. If it is not given, extended Reinhard uses the
+    /// largest luminance in the image and Hable uses 11.2.
+    pub white_point: Option<Float>
+}
+
+const HABLE_WHITE_POINT: Float = 11.2;
+
+impl ToneMapOperator {
+    pub const ALL: [ToneMapOperator; 5] = [ToneMapOperator::Clamp, ToneMapOperator::Reinhard,
+        ToneMapOperator::ReinhardExtended, ToneMapOperator::Aces, ToneMapOperator::Hable];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneMapOperator::Clamp => "clamp",
+            ToneMapOperator::Reinhard => "reinhard",
+            ToneMapOperator::ReinhardExtended => "reinhard-extended",
+            ToneMapOperator::Aces => "aces",
+            ToneMapOperator::Hable => "hable"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ToneMapOperator> {
+        ToneMapOperator::ALL.iter().copied().find(|operator| operator.name() == name)
+    }
+}
+
+impl fmt::Display for ToneMapOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Default for ToneMapping {
+    fn default() -> ToneMapping {
+        ToneMapping { operator: ToneMapOperator::Clamp, exposure: 0.0, white_point: None }
+    }
+}
+
+/// Relative luminance of a linear sRGB color.
+pub fn luminance(color: Vec3) -> Float {
+    0.2126 * color.r() + 0.7152 * color.g() + 0.0722 * color.b()
+}
+
+/// Scales `color` so that its luminance becomes `mapped(luminance)`.
+fn map_luminance(color: Vec3, mapped: impl Fn(Float) -> Float) -> Vec3 {
+    let l = luminance(color);
+    if l > 0.0 { (mapped(l) / l) * color } else { Vec3::zero() }
+}
+
+fn aces(x: Float) -> Float {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    (x * (a * x + b)) / (x * (c * x + d) + e)
+}
+
+fn hable(x: Float) -> Float {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+/// The sRGB opto-electronic transfer function, mapping linear values in [0, 1] to encoded values.
+fn srgb_oetf(value: Float) -> Float {
+    if value <= 0.0031308 {
+        12.92 * value
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl ToneMapping {
+    /// Maps a linear color to display values in [0, 1], before the sRGB transfer function. `white_point` is the
+    /// white point resolved for the image.
+    fn map(&self, color: Vec3, white_point: Float) -> Vec3 {
+        let color = (2.0 as Float).powf(self.exposure) * color;
+        let color = Vec3::new(color.r().max(0.0), color.g().max(0.0), color.b().max(0.0));
+        let mapped = match self.operator {
+            ToneMapOperator::Clamp => color,
+            ToneMapOperator::Reinhard => map_luminance(color, |l| l / (1.0 + l)),
+            ToneMapOperator::ReinhardExtended => {
+                let white_squared = white_point * white_point;
+                map_luminance(color, |l| l * (1.0 + l / white_squared) / (1.0 + l))
+            },
+            ToneMapOperator::Aces => Vec3::new(aces(color.r()), aces(color.g()), aces(color.b())),
+            ToneMapOperator::Hable => {
+                // the curve is applied with an exposure bias of 2, as in the original presentation
+                (1.0 / hable(white_point)) * Vec3::new(hable(2.0 * color.r()), hable(2.0 * color.g()), hable(2.0 * color.b()))
+            }
+        };
+        Vec3::new(mapped.r().min(1.0), mapped.g().min(1.0), mapped.b().min(1.0))
+    }
+
+    fn white_point(&self, image: &Image) -> Float {
+        match (self.white_point, self.operator) {
+            (Some(white_point), _) => white_point,
+            (None, ToneMapOperator::Hable) => HABLE_WHITE_POINT,
+            (None, ToneMapOperator::ReinhardExtended) => {
+                let exposure = (2.0 as Float).powf(self.exposure);
+                let max = (0..image.height()).flat_map(|y| image.row(y).iter())
+                    .map(|pixel| exposure * luminance(*pixel)).fold(0.0, Float::max);
+                if max > 0.0 { max } else { 1.0 }
+            },
+            (None, _) => 1.0
+        }
+    }
+
+    /// Converts the image to 8 bit sRGB.
+    pub fn apply(&self, image: &Image) -> Vec<u8> {
+        let white_point = self.white_point(image);
+        let mut data = Vec::with_capacity((image.width() * image.height() * 3) as usize);
+        for y in 0..image.height() {
+            for pixel in image.row(y) {
+                let mapped = self.map(*pixel, white_point);
+                for channel in 0..3 {
+                    data.push((srgb_oetf(mapped[channel]) * 255.0 + 0.5) as u8);
+                }
+            }
+        }
+        data
+    }
+}
@@ -0,0 +1,35 @@
+//! A toy path tracer. Scenes are built in code (`scene::Scene`) or loaded from `.scene` files, configured with
+//! `settings::RenderSettings` and rendered with `render`, which accumulates the samples in a
+//! `framebuffer::Framebuffer` and writes the requested outputs. `RenderSession` runs a render in the background,
+//! reporting its progress and allowing it to be paused and cancelled, and `distributed::Coordinator` renders on
+//! worker processes connected over TCP.
+#![allow(clippy::redundant_field_names, clippy::needless_return, clippy::upper_case_acronyms,
+    clippy::manual_range_contains, clippy::needless_range_loop, clippy::manual_swap, clippy::manual_clamp,
+    clippy::manual_map, clippy::needless_borrow, clippy::float_equality_without_abs, clippy::too_many_arguments,
+    clippy::len_without_is_empty)]
+
+extern crate png;
+
+pub mod math;
+pub mod hitable;
+pub mod camera;
+pub mod material;
+pub mod texture;
+pub mod image;
+pub mod aov;
+pub mod denoise;
+pub mod adaptive;
+pub mod framebuffer;
+pub mod sampler;
+pub mod filter;
+pub mod settings;
+pub mod scene;
+pub mod render;
+pub mod checkpoint;
+pub mod distributed;
+
+pub use crate::camera::{Camera, CameraSettings};
+pub use crate::framebuffer::Framebuffer;
+pub use crate::render::{render, Progress, RenderReport, RenderSession};
+pub use crate::scene::{Scene, SceneSettings};
+pub use crate::settings::RenderSettings;
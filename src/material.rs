@@ -1,14 +1,47 @@
+use std::ops;
+
 use crate::math::*;
 use crate::hitable::*;
 use crate::texture::*;
 
+/// Describes the lobes of a BSDF, or the lobe a sampled direction was generated from.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BsdfFlags(u8);
+
+/// A direction sampled from a BSDF. `f` is the value of the BSDF (without the cosine term) and `pdf` the solid
+/// angle density of `wi`. For specular lobes both are relative to a delta distribution and only their ratio is meaningful.
+#[derive(Copy, Clone)]
+pub struct BsdfSample {
+    pub wi: Vec3,
+    pub f: Vec3,
+    pub pdf: Float,
+    pub flags: BsdfFlags
+}
+
+/// Materials are described by their BSDF. All directions are normalized and in world space: `wo` points from the
+/// surface towards the viewer and `wi` towards the light.
 pub trait Material {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, attenuation: &mut Vec3, scattered: &mut Ray, textures: &[Box<dyn Texture>]) -> bool;
-    fn emitted(&self, _u: f32, _v: f32, _p: &Vec3, _textures: &[Box<dyn Texture>]) -> Vec3 {
+    /// Returns the BSDF for the given pair of directions; zero for specular lobes.
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord, textures: &[Box<dyn Texture>]) -> Vec3;
+    /// Samples an incident direction using the two uniform random numbers in `u`. Returns `None` if the
+    /// path should be terminated.
+    fn sample(&self, wo: Vec3, u: [Float; 2], rec: &HitRecord, textures: &[Box<dyn Texture>]) -> Option<BsdfSample>;
+    /// Returns the density with which `sample` generates `wi`; zero for specular lobes.
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Float;
+    /// Returns the lobes of the BSDF.
+    fn flags(&self) -> BsdfFlags;
+    fn emitted(&self, _u: Float, _v: Float, _p: &Vec3, _textures: &[Box<dyn Texture>]) -> Vec3 {
         return Vec3::zero();
     }
+    /// Returns the reflectance of the surface at the hit point, as written to the albedo AOV.
+    fn albedo(&self, _rec: &HitRecord, _textures: &[Box<dyn Texture>]) -> Vec3 {
+        Vec3::one()
+    }
 }
 
+/// Index of a material in the material list of a scene.
+pub type MaterialId = usize;
+
 #[derive(Copy, Clone)]
 pub enum Materials {
     Lambertian(Lambertian),
@@ -22,6 +55,7 @@ pub struct Lambertian {
     pub albedo: TextureId
 }
 
+/// A metal with a Phong lobe around the mirror direction; a roughness of zero gives a perfect mirror.
 #[derive(Copy, Clone)]
 pub struct Metal {
     pub albedo: TextureId,
@@ -38,6 +72,50 @@ pub struct DiffuseLight {
     pub emit: TextureId
 }
 
+impl BsdfFlags {
+    pub const NONE: BsdfFlags = BsdfFlags(0);
+    pub const REFLECTION: BsdfFlags = BsdfFlags(1);
+    pub const TRANSMISSION: BsdfFlags = BsdfFlags(2);
+    pub const DIFFUSE: BsdfFlags = BsdfFlags(4);
+    pub const GLOSSY: BsdfFlags = BsdfFlags(8);
+    pub const SPECULAR: BsdfFlags = BsdfFlags(16);
+
+    pub fn contains(self, other: BsdfFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: BsdfFlags) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Returns true if all lobes are delta distributions, so the BSDF can't be evaluated for directions
+    /// that weren't sampled from it.
+    pub fn is_specular(self) -> bool {
+        self.contains(BsdfFlags::SPECULAR) && !self.intersects(BsdfFlags::DIFFUSE | BsdfFlags::GLOSSY)
+    }
+}
+
+impl ops::BitOr for BsdfFlags {
+    type Output = BsdfFlags;
+
+    fn bitor(self, other: BsdfFlags) -> BsdfFlags {
+        BsdfFlags(self.0 | other.0)
+    }
+}
+
+/// Returns the surface normal flipped to the side of `wo`, so that surfaces can be hit from both sides.
+fn facing_normal(wo: Vec3, rec: &HitRecord) -> Vec3 {
+    if Vec3::dot(wo, rec.normal) < 0.0 { -rec.normal } else { rec.normal }
+}
+
+/// Maps `u` to a direction around `w` with density proportional to `cos(theta)^exponent`.
+fn sample_cosine_power(w: Vec3, exponent: Float, u: [Float; 2]) -> Vec3 {
+    let cos_theta = u[0].powf(1.0 / (exponent + 1.0));
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u[1];
+    let (s, t) = Vec3::orthonormal_basis(w);
+    phi.cos() * sin_theta * s + phi.sin() * sin_theta * t + cos_theta * w
+}
 
 impl Lambertian {
     pub fn new(albedo: TextureId) -> Lambertian {
@@ -49,6 +127,15 @@ impl Metal {
     pub fn new(albedo: TextureId, roughness: Float) -> Metal {
         Metal { albedo: albedo, roughness: roughness }
     }
+
+    fn is_mirror(&self) -> bool {
+        self.roughness < 1e-3
+    }
+
+    /// Phong exponent of the lobe; a roughness of `sqrt(2 / (n + 2))` corresponds to the exponent `n`.
+    fn exponent(&self) -> Float {
+        (2.0 / (self.roughness * self.roughness) - 2.0).max(0.0)
+    }
 }
 
 impl Dielectric {
@@ -64,16 +151,43 @@ impl DiffuseLight {
 }
 
 impl Material for Materials {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, attenuation: &mut Vec3, scattered: &mut Ray, textures: &[Box<dyn Texture>]) -> bool {
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord, textures: &[Box<dyn Texture>]) -> Vec3 {
         match self {
-            Materials::Lambertian(lambertian) => lambertian.scatter(ray_in, rec, attenuation, scattered, textures),
-            Materials::Metal(metal) => metal.scatter(ray_in, rec, attenuation, scattered, textures),
-            Materials::Dielectric(dielectric) => dielectric.scatter(ray_in, rec, attenuation, scattered, textures),
-            Materials::DiffuseLight(diffuse_light) => diffuse_light.scatter(ray_in, rec, attenuation, scattered, textures)
+            Materials::Lambertian(lambertian) => lambertian.eval(wo, wi, rec, textures),
+            Materials::Metal(metal) => metal.eval(wo, wi, rec, textures),
+            Materials::Dielectric(dielectric) => dielectric.eval(wo, wi, rec, textures),
+            Materials::DiffuseLight(diffuse_light) => diffuse_light.eval(wo, wi, rec, textures)
         }
     }
 
-    fn emitted(&self, u: f32, v: f32, p: &Vec3, textures: &[Box<dyn Texture>]) -> Vec3 {
+    fn sample(&self, wo: Vec3, u: [Float; 2], rec: &HitRecord, textures: &[Box<dyn Texture>]) -> Option<BsdfSample> {
+        match self {
+            Materials::Lambertian(lambertian) => lambertian.sample(wo, u, rec, textures),
+            Materials::Metal(metal) => metal.sample(wo, u, rec, textures),
+            Materials::Dielectric(dielectric) => dielectric.sample(wo, u, rec, textures),
+            Materials::DiffuseLight(diffuse_light) => diffuse_light.sample(wo, u, rec, textures)
+        }
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Float {
+        match self {
+            Materials::Lambertian(lambertian) => lambertian.pdf(wo, wi, rec),
+            Materials::Metal(metal) => metal.pdf(wo, wi, rec),
+            Materials::Dielectric(dielectric) => dielectric.pdf(wo, wi, rec),
+            Materials::DiffuseLight(diffuse_light) => diffuse_light.pdf(wo, wi, rec)
+        }
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        match self {
+            Materials::Lambertian(lambertian) => lambertian.flags(),
+            Materials::Metal(metal) => metal.flags(),
+            Materials::Dielectric(dielectric) => dielectric.flags(),
+            Materials::DiffuseLight(diffuse_light) => diffuse_light.flags()
+        }
+    }
+
+    fn emitted(&self, u: Float, v: Float, p: &Vec3, textures: &[Box<dyn Texture>]) -> Vec3 {
         match self {
             Materials::Lambertian(lambertian) => lambertian.emitted(u, v, p, textures),
             Materials::Metal(metal) => metal.emitted(u, v, p, textures),
@@ -81,25 +195,95 @@ impl Material for Materials {
             Materials::DiffuseLight(diffuse_light) => diffuse_light.emitted(u, v, p, textures)
         }
     }
+
+    fn albedo(&self, rec: &HitRecord, textures: &[Box<dyn Texture>]) -> Vec3 {
+        match self {
+            Materials::Lambertian(lambertian) => lambertian.albedo(rec, textures),
+            Materials::Metal(metal) => metal.albedo(rec, textures),
+            Materials::Dielectric(dielectric) => dielectric.albedo(rec, textures),
+            Materials::DiffuseLight(diffuse_light) => diffuse_light.albedo(rec, textures)
+        }
+    }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray_in: &Ray, rec: &HitRecord, attenuation: &mut Vec3, scattered: &mut Ray,
-            textures: &[Box<dyn Texture>]) -> bool {
-        let target = rec.p + rec.normal + random_in_unit_sphere();
-        *scattered = Ray::new(rec.p, target - rec.p);
-        *attenuation = textures[self.albedo].value(0.0, 0.0, &rec.p, textures);
-        true
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord, textures: &[Box<dyn Texture>]) -> Vec3 {
+        if Vec3::dot(wi, facing_normal(wo, rec)) <= 0.0 {
+            return Vec3::zero();
+        }
+        (1.0 / PI) * textures[self.albedo].value(rec.u, rec.v, &rec.p, textures)
+    }
+
+    fn sample(&self, wo: Vec3, u: [Float; 2], rec: &HitRecord, textures: &[Box<dyn Texture>]) -> Option<BsdfSample> {
+        let wi = sample_cosine_power(facing_normal(wo, rec), 1.0, u);
+        let pdf = self.pdf(wo, wi, rec);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { wi: wi, f: self.eval(wo, wi, rec, textures), pdf: pdf, flags: self.flags() })
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Float {
+        Vec3::dot(wi, facing_normal(wo, rec)).max(0.0) / PI
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION
+    }
+
+    fn albedo(&self, rec: &HitRecord, textures: &[Box<dyn Texture>]) -> Vec3 {
+        textures[self.albedo].value(rec.u, rec.v, &rec.p, textures)
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, attenuation: &mut Vec3, scattered: &mut Ray,
-            textures: &[Box<dyn Texture>]) -> bool {
-        let reflected = Vec3::reflect(Vec3::normalize(ray_in.direction()), rec.normal);
-        *scattered = Ray::new(rec.p, reflected + self.roughness * random_in_unit_sphere());
-        *attenuation = textures[self.albedo].value(0.0, 0.0, &rec.p, textures);
-        Vec3::dot(scattered.direction(), rec.normal) > 0.0
+    /// Normalized Phong BRDF, `(n + 2) / 2pi * cos(alpha)^n` with `alpha` the angle to the mirror direction.
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord, textures: &[Box<dyn Texture>]) -> Vec3 {
+        let normal = facing_normal(wo, rec);
+        if self.is_mirror() || Vec3::dot(wi, normal) <= 0.0 {
+            return Vec3::zero();
+        }
+        let cos_alpha = Vec3::dot(Vec3::reflect(-wo, normal), wi).max(0.0);
+        let exponent = self.exponent();
+        ((exponent + 2.0) / (2.0 * PI) * cos_alpha.powf(exponent)) * textures[self.albedo].value(rec.u, rec.v, &rec.p, textures)
+    }
+
+    fn sample(&self, wo: Vec3, u: [Float; 2], rec: &HitRecord, textures: &[Box<dyn Texture>]) -> Option<BsdfSample> {
+        let normal = facing_normal(wo, rec);
+        let reflected = Vec3::reflect(-wo, normal);
+        if self.is_mirror() {
+            let cosine = Vec3::dot(reflected, normal);
+            let albedo = textures[self.albedo].value(rec.u, rec.v, &rec.p, textures);
+            return Some(BsdfSample { wi: reflected, f: (1.0 / cosine) * albedo, pdf: 1.0, flags: self.flags() });
+        }
+        let wi = sample_cosine_power(reflected, self.exponent(), u);
+        // directions below the surface are absorbed
+        if Vec3::dot(wi, normal) <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { wi: wi, f: self.eval(wo, wi, rec, textures), pdf: self.pdf(wo, wi, rec), flags: self.flags() })
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Float {
+        let normal = facing_normal(wo, rec);
+        if self.is_mirror() || Vec3::dot(wi, normal) <= 0.0 {
+            return 0.0;
+        }
+        let cos_alpha = Vec3::dot(Vec3::reflect(-wo, normal), wi).max(0.0);
+        let exponent = self.exponent();
+        (exponent + 1.0) / (2.0 * PI) * cos_alpha.powf(exponent)
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        if self.is_mirror() {
+            BsdfFlags::SPECULAR | BsdfFlags::REFLECTION
+        } else {
+            BsdfFlags::GLOSSY | BsdfFlags::REFLECTION
+        }
+    }
+
+    fn albedo(&self, rec: &HitRecord, textures: &[Box<dyn Texture>]) -> Vec3 {
+        textures[self.albedo].value(rec.u, rec.v, &rec.p, textures)
     }
 }
 
@@ -110,40 +294,78 @@ fn schlick(cosine: Float, refractive_index: Float) -> Float {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, attenuation: &mut Vec3, scattered: &mut Ray,
-            _textures: &[Box<dyn Texture>]) -> bool {
-        let reflected = Vec3::reflect(ray_in.direction(), rec.normal);
-        *attenuation = Vec3::one();
-
-        let (outward_normal, ni_over_nt, cosine) = if Vec3::dot(ray_in.direction(), rec.normal) > 0.0 {
-            (-rec.normal,
-                self.refractive_index,
-                self.refractive_index * Vec3::dot(ray_in.direction(), rec.normal) / ray_in.direction().length())
+    fn eval(&self, _wo: Vec3, _wi: Vec3, _rec: &HitRecord, _textures: &[Box<dyn Texture>]) -> Vec3 {
+        Vec3::zero()
+    }
+
+    /// Chooses between reflection and refraction with the probability given by the Fresnel term.
+    fn sample(&self, wo: Vec3, u: [Float; 2], rec: &HitRecord, _textures: &[Box<dyn Texture>]) -> Option<BsdfSample> {
+        let cos_o = Vec3::dot(wo, rec.normal);
+        let (outward_normal, ni_over_nt, cosine) = if cos_o < 0.0 {
+            (-rec.normal, self.refractive_index, -self.refractive_index * cos_o)
         } else {
-            (rec.normal,
-                1.0 / self.refractive_index,
-                -self.refractive_index * Vec3::dot(ray_in.direction(), rec.normal) / ray_in.direction().length())
+            (rec.normal, 1.0 / self.refractive_index, cos_o)
         };
+        let reflect_probability = match Vec3::refract(-wo, outward_normal, ni_over_nt) {
+            Some(refracted) => {
+                let reflect_probability = schlick(cosine, self.refractive_index);
+                if u[0] >= reflect_probability {
+                    let wi = Vec3::normalize(refracted);
+                    let transmittance = 1.0 - reflect_probability;
+                    return Some(BsdfSample {
+                        wi: wi,
+                        f: (transmittance / Vec3::dot(wi, rec.normal).abs()) * Vec3::one(),
+                        pdf: transmittance,
+                        flags: BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION
+                    });
+                }
+                reflect_probability
+            },
+            // total internal reflection
+            None => 1.0
+        };
+        let wi = Vec3::reflect(-wo, rec.normal);
+        Some(BsdfSample {
+            wi: wi,
+            f: (reflect_probability / Vec3::dot(wi, rec.normal).abs()) * Vec3::one(),
+            pdf: reflect_probability,
+            flags: BsdfFlags::SPECULAR | BsdfFlags::REFLECTION
+        })
+    }
 
-       if let Some(refracted) = Vec3::refract(ray_in.direction(), outward_normal, ni_over_nt) {
-            if random() < schlick(cosine, self.refractive_index) {
-                *scattered = Ray::new(rec.p, reflected);
-            } else {
-                *scattered = Ray::new(rec.p, refracted);
-            }
-        } else {
-            *scattered = Ray::new(rec.p, reflected);
-        }
-        true
+    fn pdf(&self, _wo: Vec3, _wi: Vec3, _rec: &HitRecord) -> Float {
+        0.0
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags::SPECULAR | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION
     }
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, _ray_in: &Ray, _rec: &HitRecord, _attenuation: &mut Vec3, _scattered: &mut Ray, _textures: &[Box<dyn Texture>]) -> bool {
-        return false;
+    fn eval(&self, _wo: Vec3, _wi: Vec3, _rec: &HitRecord, _textures: &[Box<dyn Texture>]) -> Vec3 {
+        Vec3::zero()
+    }
+
+    fn sample(&self, _wo: Vec3, _u: [Float; 2], _rec: &HitRecord, _textures: &[Box<dyn Texture>]) -> Option<BsdfSample> {
+        None
+    }
+
+    fn pdf(&self, _wo: Vec3, _wi: Vec3, _rec: &HitRecord) -> Float {
+        0.0
     }
 
-    fn emitted(&self, u: f32, v: f32, p: &Vec3, textures: &[Box<dyn Texture>]) -> Vec3 {
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags::NONE
+    }
+
+    fn emitted(&self, u: Float, v: Float, p: &Vec3, textures: &[Box<dyn Texture>]) -> Vec3 {
         return textures[self.emit].value(u, v, p, textures);
     }
-}
\ No newline at end of file
+
+    /// The emission clamped to [0, 1], so that lights show up in the albedo like a white surface.
+    fn albedo(&self, rec: &HitRecord, textures: &[Box<dyn Texture>]) -> Vec3 {
+        let emitted = self.emitted(rec.u, rec.v, &rec.p, textures);
+        Vec3::new(emitted.r().min(1.0), emitted.g().min(1.0), emitted.b().min(1.0))
+    }
+}
@@ -0,0 +1,181 @@
+use std::ops;
+use crate::math::{Float, Vec3};
+
+/// Row-major 4x4 matrix acting on column vectors.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[Float; 4]; 4]
+}
+
+/// An affine transform together with its inverse.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    matrix: Matrix4,
+    inverse: Matrix4
+}
+
+impl Matrix4 {
+    pub fn new(m: [[Float; 4]; 4]) -> Matrix4 {
+        Matrix4 { m: m }
+    }
+
+    pub fn identity() -> Matrix4 {
+        Matrix4::scale(Vec3::one())
+    }
+
+    pub fn translation(offset: Vec3) -> Matrix4 {
+        Matrix4 { m: [
+            [1.0, 0.0, 0.0, offset.x()],
+            [0.0, 1.0, 0.0, offset.y()],
+            [0.0, 0.0, 1.0, offset.z()],
+            [0.0, 0.0, 0.0, 1.0]
+        ] }
+    }
+
+    pub fn scale(factors: Vec3) -> Matrix4 {
+        Matrix4 { m: [
+            [factors.x(), 0.0, 0.0, 0.0],
+            [0.0, factors.y(), 0.0, 0.0],
+            [0.0, 0.0, factors.z(), 0.0],
+            [0.0, 0.0, 0.0, 1.0]
+        ] }
+    }
+
+    /// Counter-clockwise rotation by `degrees` around `axis` (when looking against the axis direction).
+    pub fn rotation(axis: Vec3, degrees: Float) -> Matrix4 {
+        let a = Vec3::normalize(axis);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        Matrix4 { m: [
+            [t * x * x + cos, t * x * y - sin * z, t * x * z + sin * y, 0.0],
+            [t * x * y + sin * z, t * y * y + cos, t * y * z - sin * x, 0.0],
+            [t * x * z - sin * y, t * y * z + sin * x, t * z * z + cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0]
+        ] }
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut result = *self;
+        for (i, row) in result.m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        result
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting; returns `None` if the matrix is singular.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+        for column in 0..4 {
+            let pivot = (column..4).max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs())).unwrap();
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inv.swap(column, pivot);
+            let scale = 1.0 / a[column][column];
+            for k in 0..4 {
+                a[column][k] *= scale;
+                inv[column][k] *= scale;
+            }
+            for row in 0..4 {
+                if row != column {
+                    let factor = a[row][column];
+                    for k in 0..4 {
+                        a[row][k] -= factor * a[column][k];
+                        inv[row][k] -= factor * inv[column][k];
+                    }
+                }
+            }
+        }
+        Some(Matrix4 { m: inv })
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3];
+        let y = m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3];
+        let z = m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3];
+        let w = m[3][0] * p.x() + m[3][1] * p.y() + m[3][2] * p.z() + m[3][3];
+        if w == 1.0 { Vec3::new(x, y, z) } else { Vec3::new(x / w, y / w, z / w) }
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z())
+    }
+}
+
+impl ops::Mul<Matrix4> for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Matrix4 { m: m }
+    }
+}
+
+impl Transform {
+    /// Panics if `matrix` can not be inverted.
+    pub fn new(matrix: Matrix4) -> Transform {
+        match matrix.inverse() {
+            Some(inverse) => Transform { matrix: matrix, inverse: inverse },
+            None => panic!("transform matrices need to be invertible.")
+        }
+    }
+
+    pub fn identity() -> Transform {
+        Transform { matrix: Matrix4::identity(), inverse: Matrix4::identity() }
+    }
+
+    pub fn translate(offset: Vec3) -> Transform {
+        Transform { matrix: Matrix4::translation(offset), inverse: Matrix4::translation(-offset) }
+    }
+
+    /// Panics if one of the factors is zero.
+    pub fn scale(factors: Vec3) -> Transform {
+        if factors.x() == 0.0 || factors.y() == 0.0 || factors.z() == 0.0 {
+            panic!("scale factors must not be zero.");
+        }
+        let inverse = Vec3::new(1.0 / factors.x(), 1.0 / factors.y(), 1.0 / factors.z());
+        Transform { matrix: Matrix4::scale(factors), inverse: Matrix4::scale(inverse) }
+    }
+
+    pub fn rotate(axis: Vec3, degrees: Float) -> Transform {
+        let matrix = Matrix4::rotation(axis, degrees);
+        Transform { matrix: matrix, inverse: matrix.transpose() }
+    }
+
+    /// Returns the transform that applies `self` first and then `next`.
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform { matrix: next.matrix * self.matrix, inverse: self.inverse * next.inverse }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform { matrix: self.inverse, inverse: self.matrix }
+    }
+
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    /// Transforms a surface normal with the inverse transpose; the result is not normalized.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        self.inverse.transpose().transform_vector(n)
+    }
+}
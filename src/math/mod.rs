@@ -1,11 +1,12 @@
 mod vec3;
+mod matrix;
 
 use std::f32;
-use rand::prelude::*;
 
 pub use self::vec3::Vec3;
-pub use self::vec3::random_in_unit_disk;
-pub use self::vec3::random_in_unit_sphere;
+pub use self::matrix::Transform;
+pub use self::vec3::sample_unit_disk;
+pub use self::vec3::sample_unit_vector;
 
 pub type Float = f32;
 pub const MAX_FLOAT: Float = f32::MAX;
@@ -16,13 +17,15 @@ pub const PI: Float = f32::consts::PI;
 #[derive(Copy, Clone)]
 pub struct Ray {
     origin: Vec3,
-    direction: Vec3
+    direction: Vec3,
+    /// Point in time within the camera shutter interval at which the ray is traced.
+    time: Float
 }
 
 #[allow(dead_code)]
 impl Ray {
-    pub fn new(o: Vec3, d: Vec3) -> Ray {
-        Ray { origin: o, direction: d }
+    pub fn new(o: Vec3, d: Vec3, time: Float) -> Ray {
+        Ray { origin: o, direction: d, time: time }
     }
 
     pub fn eval(self, t: Float) -> Vec3 {
@@ -36,8 +39,18 @@ impl Ray {
     pub fn direction(self) -> Vec3 {
         self.direction
     }
+
+    pub fn time(self) -> Float {
+        self.time
+    }
 }
 
-pub fn random() -> Float {
-    rand::thread_rng().gen()
+/// FNV-1a hash of `bytes`, continuing from the hash `state` of earlier data (0 for none). Unlike the hashers of
+/// the standard library it is stable across platforms and releases, so it can be stored in files.
+pub fn hash_bytes(state: u64, bytes: &[u8]) -> u64 {
+    let mut hash = state ^ 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
 }
\ No newline at end of file
@@ -1,7 +1,6 @@
 use std::ops;
 use std::fmt;
-use rand::prelude::*;
-use crate::math::Float;
+use crate::math::{Float, PI};
 
 #[derive(Copy, Clone, Default)]
 pub struct Vec3 {
@@ -50,6 +49,13 @@ impl Vec3 {
         }
     }
 
+    /// Returns two unit vectors that form a right-handed orthonormal basis with the unit vector `w`.
+    pub fn orthonormal_basis(w: Vec3) -> (Vec3, Vec3) {
+        let a = if w.e[0].abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let v = Vec3::normalize(Vec3::cross(w, a));
+        (Vec3::cross(v, w), v)
+    }
+
     pub fn length(self) -> Float {
         let squared_length = self.e[0] * self.e[0] + self.e[1] * self.e[1] + self.e[2] * self.e[2];
         squared_length.sqrt()
@@ -181,22 +187,25 @@ impl fmt::Display for Vec3 {
     }
 }
 
-pub fn random_in_unit_sphere() -> Vec3 {
-    let mut p: Vec3;
-    loop {
-        p = 2.0 * Vec3::new(rand::thread_rng().gen(), rand::thread_rng().gen(), rand::thread_rng().gen()) - Vec3::one();
-        if p.length_squared() < 1.0 {
-            return p
-        }
+/// Maps the uniform numbers `u` to a point in the unit disk in the xy-plane, with the concentric mapping of Shirley
+/// and Chiu, which keeps strata of `u` compact.
+pub fn sample_unit_disk(u: [Float; 2]) -> Vec3 {
+    let (a, b) = (2.0 * u[0] - 1.0, 2.0 * u[1] - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return Vec3::zero();
     }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, 0.25 * PI * (b / a))
+    } else {
+        (b, 0.5 * PI - 0.25 * PI * (a / b))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
-pub fn random_in_unit_disk() -> Vec3 {
-    let mut p: Vec3;
-    loop {
-        p = 2.0 * Vec3::new(rand::thread_rng().gen(), rand::thread_rng().gen(), 0.0) - Vec3::new(1.0, 1.0, 0.0);
-        if p.length_squared() < 1.0 {
-            return p
-        }
-    }
-}
\ No newline at end of file
+/// Maps the uniform numbers `u` to a uniformly distributed direction.
+pub fn sample_unit_vector(u: [Float; 2]) -> Vec3 {
+    let z = 1.0 - 2.0 * u[0];
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u[1];
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
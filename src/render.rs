@@ -0,0 +1,340 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use crate::math::*;
+use crate::hitable::*;
+use crate::material::*;
+use crate::camera::*;
+use crate::settings::*;
+use crate::scene::*;
+use crate::image::*;
+use crate::aov::*;
+use crate::denoise::*;
+use crate::framebuffer::*;
+use crate::sampler::*;
+
+mod session;
+pub(crate) use crate::render::session::Control;
+pub use crate::render::session::{Progress, RenderSession, Snapshot};
+pub(crate) use crate::render::session::{ProgressCallback, TileCallback};
+
+/// Sampling parameters of a single pass over the image.
+#[derive(Copy, Clone)]
+pub(crate) struct Pass {
+    /// Samples added to every pixel that is not finished yet.
+    pub samples: usize,
+    /// Render threads stop taking tiles after this point in time.
+    pub deadline: Option<Instant>,
+    /// Whether the AOVs of the first hit are accumulated.
+    pub aovs: bool
+}
+
+/// Summary of a finished render.
+#[derive(Copy, Clone, Debug)]
+pub struct RenderReport {
+    /// Time spent rendering, without denoising and writing the final outputs.
+    pub render_time: Duration,
+    /// Number of passes over the image.
+    pub passes: usize,
+    /// Why the last pass was the last one.
+    pub stop_reason: &'static str,
+    /// Samples per pixel, averaged over the image.
+    pub average_samples: Float,
+    /// Mean relative error of the pixels; see `Framebuffer::noise`.
+    pub noise: Float,
+    /// Time spent denoising, if the render was denoised.
+    pub denoise_time: Option<Duration>
+}
+
+impl Pass {
+    /// The passes of a render that started at `start`.
+    pub(crate) fn new(settings: &RenderSettings, start: Instant) -> Pass {
+        Pass {
+            samples: settings.pass_samples(),
+            deadline: settings.time_limit.map(|limit| start + Duration::from_secs_f32(limit)),
+            aovs: !aovs_to_render(settings).is_empty()
+        }
+    }
+}
+
+impl RenderReport {
+    pub(crate) fn new(render_time: Duration, passes: usize, stop_reason: &'static str, framebuffer: &Framebuffer) -> RenderReport {
+        RenderReport {
+            render_time: render_time,
+            passes: passes,
+            stop_reason: stop_reason,
+            average_samples: framebuffer.total_samples() as Float / (framebuffer.width() * framebuffer.height()) as Float,
+            noise: framebuffer.noise(),
+            denoise_time: None
+        }
+    }
+}
+
+/// Splits the image into tiles of `settings.tile_size`, the units the render threads take.
+pub(crate) fn image_tiles(settings: &RenderSettings) -> Vec<RenderTile> {
+    let (width, height, tile_size) = (settings.width, settings.height, settings.tile_size);
+    let mut tiles = Vec::new();
+    for x in (0..width).step_by(tile_size as usize) {
+        for y in (0..height).step_by(tile_size as usize) {
+            let tile_width = if width - x >= tile_size { tile_size } else { width - x };
+            let tile_height = if height - y >= tile_size { tile_size } else { height - y };
+            tiles.push(RenderTile { left: x, top: y, width: tile_width, height: tile_height });
+        }
+    }
+    tiles
+}
+
+/// Returns why the render stops after the pass that has just finished, if it does.
+pub(crate) fn stop_reason(settings: &RenderSettings, pass: &Pass, framebuffer: &Framebuffer) -> Option<&'static str> {
+    if pass.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        Some("time limit reached")
+    } else if settings.noise_target.is_some_and(|target| {
+        // the noise is only estimated from two samples per pixel on
+        let pixels = framebuffer.width() as usize * framebuffer.height() as usize;
+        framebuffer.total_samples() >= 2 * pixels && framebuffer.noise() <= target
+    }) {
+        Some("noise target reached")
+    } else if framebuffer.finished(settings.samples) {
+        Some("all pixels finished")
+    } else {
+        None
+    }
+}
+
+/// Power heuristic weight of a sample taken with density `pdf` when `other_pdf` could also have generated it.
+fn mis_weight(pdf: Float, other_pdf: Float) -> Float {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 { a / (a + b) } else { 0.0 }
+}
+
+/// Traces a path starting at `ray`. At every non-specular vertex a light is sampled directly, and the contributions
+/// of light samples and BSDF samples that hit an emitter are combined with multiple importance sampling.
+/// Also returns the first surface that was hit, for the AOVs. Every bounce takes two pairs of numbers from
+/// `sampler`: one for the light sample and one for the BSDF sample.
+pub fn color(ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> (Vec3, Option<HitRecord>) {
+    let (world, lights, materials, textures) = (&scene.world, &scene.lights, &scene.materials, &scene.textures[..]);
+    let mut result = Vec3::zero();
+    let mut throughput = Vec3::one();
+    let mut ray = *ray;
+    // density of the BSDF sample that generated `ray`, `None` for camera rays and specular bounces
+    let mut bsdf_pdf: Option<Float> = None;
+    let mut first_hit = None;
+    for depth in 0..50 {
+        let rec = match world.hit(&ray, 0.001, MAX_FLOAT) {
+            Some(rec) => rec,
+            None => break
+        };
+        if depth == 0 {
+            first_hit = Some(rec);
+        }
+        let material = &materials[rec.material];
+        let emitted = material.emitted(rec.u, rec.v, &rec.p, textures);
+        let weight = match bsdf_pdf {
+            Some(pdf) => mis_weight(pdf, lights.pdf_value(ray.origin(), ray.direction())),
+            None => 1.0
+        };
+        result += weight * (throughput * emitted);
+
+        if depth == 49 {
+            break;
+        }
+        let wo = -Vec3::normalize(ray.direction());
+        // drawn even when unused, so that every bounce uses the same dimensions of the sampler
+        let light_u = sampler.get_2d();
+        let bsdf_u = sampler.get_2d();
+        if !material.flags().is_specular() && !lights.is_empty() {
+            let wi = Vec3::normalize(lights.random(rec.p, light_u));
+            let light_ray = Ray::new(rec.p, wi, ray.time());
+            let light_pdf = lights.pdf_value(light_ray.origin(), light_ray.direction());
+            let light_bsdf_pdf = material.pdf(wo, wi, &rec);
+            if light_pdf > 0.0 && light_bsdf_pdf > 0.0 {
+                if let Some(light_rec) = world.hit(&light_ray, 0.001, MAX_FLOAT) {
+                    let light_emitted = materials[light_rec.material].emitted(light_rec.u, light_rec.v, &light_rec.p, textures);
+                    let f = material.eval(wo, wi, &rec, textures);
+                    let weight = mis_weight(light_pdf, light_bsdf_pdf) * Vec3::dot(wi, rec.normal).abs() / light_pdf;
+                    result += weight * (throughput * f * light_emitted);
+                }
+            }
+        }
+
+        let sample = match material.sample(wo, bsdf_u, &rec, textures) {
+            Some(sample) => sample,
+            None => break
+        };
+        bsdf_pdf = if sample.flags.contains(BsdfFlags::SPECULAR) { None } else { Some(sample.pdf) };
+        throughput = throughput * ((Vec3::dot(sample.wi, rec.normal).abs() / sample.pdf) * sample.f);
+        ray = Ray::new(rec.p, sample.wi, ray.time());
+    }
+    (result, first_hit)
+}
+
+/// Renders tiles of the current pass until there are none left, the deadline has passed or the render is cancelled,
+/// and sends every finished tile over `channel`.
+fn render_thread(channel: Sender<RenderTile>, settings: Arc<RenderSettings>, tiles: Arc<Mutex<Vec<RenderTile>>>,
+        scene: Arc<Scene>, camera: Arc<Camera>, out: Arc<RwLock<Framebuffer>>, control: Arc<Control>, pass: Pass) {
+    let mut sampler = settings.sampler.create(settings.samples, settings.seed);
+    loop {
+        if !control.wait() || pass.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return;
+        }
+        let t = tiles.lock().unwrap().pop();
+        if let Some(tile) = t {
+            let (mut pixels, mut splats) = {
+                let out = out.read().unwrap();
+                (out.read(&tile), out.read_splats(&tile))
+            };
+            // a cancelled tile is dropped rather than written half done
+            if !render_tile(&settings, &scene, &camera, sampler.as_mut(), &tile, &mut pixels, &mut splats, pass,
+                    &control) {
+                return;
+            }
+            out.write().unwrap().write(&tile, &pixels, splats);
+            channel.send(tile).unwrap();
+        } else {
+            return;
+        }
+    }
+}
+
+/// Adds the samples of `pass` to the `pixels` of `tile`, given row by row, and their splats to `splats`. Returns
+/// false if the render was cancelled before the tile was done.
+pub(crate) fn render_tile(settings: &RenderSettings, scene: &Scene, camera: &Camera, sampler: &mut dyn Sampler, tile: &RenderTile,
+        pixels: &mut [PixelState], splats: &mut SplatBuffer, pass: Pass, control: &Control) -> bool {
+    let (width, height) = (settings.width, settings.height);
+    let adaptive = settings.adaptive();
+    let filter = settings.filter();
+    for x in 0..tile.width {
+        if control.is_cancelled() {
+            return false;
+        }
+        for y in 0..tile.height {
+            let pixel = &mut pixels[(y * tile.width + x) as usize];
+            let global_x = x + tile.left;
+            let global_y = y + tile.top;
+            let target = (pixel.samples() + pass.samples).min(settings.samples);
+            while !pixel.converged && pixel.samples() < target {
+                sampler.start_sample(global_x, global_y, pixel.samples());
+                let [ur, vr] = sampler.get_2d();
+                let u = (global_x as Float + ur) / width as Float;
+                let v = ((height - global_y) as Float - vr) / height as Float;
+                let lens = sampler.get_2d();
+                let time = sampler.get_1d();
+                let r = camera.get_ray(u, v, lens, time);
+                let (sample, first_hit) = color(&r, scene, sampler);
+                pixel.sum += sample;
+                splats.add(global_x as Float + ur, global_y as Float + vr, sample, &filter);
+                pixel.variance.add(sample);
+                if pass.aovs {
+                    match &first_hit {
+                        Some(rec) => {
+                            let albedo = scene.materials[rec.material].albedo(rec, &scene.textures);
+                            pixel.aovs.add(Some(rec), albedo, camera.depth(rec.p));
+                        },
+                        None => pixel.aovs.add(None, Vec3::zero(), 0.0)
+                    }
+                }
+                pixel.converged = adaptive.is_some_and(|adaptive| adaptive.converged(&pixel.variance));
+            }
+        }
+    }
+    true
+}
+
+fn create_parent_dir(path: &Path) -> Result<(), String> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent)
+            .map_err(|why| format!("couldn't create output directory {}: {}", parent.display(), why)),
+        None => Ok(())
+    }
+}
+
+/// Writes the number of samples per pixel: as is to floating point formats, and relative to `max_samples` to PNGs.
+fn write_spp(samples: &Image, path: &Path, max_samples: usize, pixel: ExrPixel) -> io::Result<()> {
+    match HdrFormat::from_path(path) {
+        Some(format) => samples.write_hdr(path, format, pixel),
+        None => {
+            let scale = 1.0 / max_samples as Float;
+            let relative = Image::from_pixels(samples.width(), samples.height(),
+                (0..samples.height()).flat_map(|y| samples.row(y).iter()).map(|&samples| scale * samples).collect());
+            relative.write_png(path, &ToneMapping::default())
+        }
+    }
+}
+
+/// Writes the PNG and, if requested, the floating point copy of `color`.
+pub fn write_color(settings: &RenderSettings, color: &Image) -> Result<(), String> {
+    color.write_png(&settings.output, &settings.tone_mapping)
+        .map_err(|why| format!("couldn't write {}: {}", settings.output.display(), why))?;
+    if let Some(hdr_path) = &settings.hdr_output {
+        let format = match HdrFormat::from_path(hdr_path) {
+            Some(format) => format,
+            None => return Err(format!("{} has no floating point image extension", hdr_path.display()))
+        };
+        color.write_hdr(hdr_path, format, settings.exr_pixel)
+            .map_err(|why| format!("couldn't write {}: {}", hdr_path.display(), why))?;
+    }
+    Ok(())
+}
+
+/// The AOVs that are accumulated while rendering: the ones that are written, and the ones that guide the denoiser.
+fn aovs_to_render(settings: &RenderSettings) -> Vec<Aov> {
+    let mut aovs = if settings.aov_output.is_some() { settings.aovs.clone() } else { Vec::new() };
+    if settings.denoise.is_some() {
+        for aov in [Aov::Albedo, Aov::Normal, Aov::Depth] {
+            if !aovs.contains(&aov) {
+                aovs.push(aov);
+            }
+        }
+    }
+    aovs
+}
+
+/// Creates the directories of all outputs requested by `settings`.
+pub fn create_output_dirs(settings: &RenderSettings) -> Result<(), String> {
+    create_parent_dir(&settings.output)?;
+    let optional = [&settings.hdr_output, &settings.aov_output, &settings.spp_output, &settings.checkpoint];
+    for path in optional.iter().copied().flatten() {
+        create_parent_dir(path)?;
+    }
+    Ok(())
+}
+
+/// Denoises the finished render in `framebuffer` if requested and writes all outputs requested by `settings`.
+/// Returns the time spent denoising, if any.
+pub fn write_outputs(settings: &RenderSettings, framebuffer: &Framebuffer) -> Result<Option<Duration>, String> {
+    let mut color = framebuffer.color();
+    let aov_buffers = framebuffer.aovs(&aovs_to_render(settings));
+    let start_denoise = Instant::now();
+    if let Some(method) = settings.denoise {
+        let guide = |aov| aov_buffers.image(aov).expect("feature AOVs are rendered when denoising");
+        let features = Features { albedo: guide(Aov::Albedo), normal: guide(Aov::Normal), depth: guide(Aov::Depth) };
+        color = denoise(&color, &features, method, settings.denoise_strength, settings.threads);
+    }
+    let elapsed_denoise = start_denoise.elapsed();
+    write_color(settings, &color)?;
+
+    if let Some(aov_path) = &settings.aov_output {
+        aov_buffers.write(aov_path, &settings.aovs, &color, settings.exr_pixel)
+            .map_err(|why| format!("couldn't write {}: {}", aov_path.display(), why))?;
+    }
+    if let Some(spp_path) = &settings.spp_output {
+        write_spp(&framebuffer.samples(), spp_path, settings.samples, settings.exr_pixel)
+            .map_err(|why| format!("couldn't write {}: {}", spp_path.display(), why))?;
+    }
+    Ok(settings.denoise.map(|_| elapsed_denoise))
+}
+
+/// Renders `scene` and writes the outputs requested by `settings`, without reporting progress; see `RenderSession`
+/// for renders that are observed or controlled while they run. Fails if an output can't be written.
+pub fn render(settings: &RenderSettings, scene: Scene) -> Result<RenderReport, String> {
+    create_output_dirs(settings)?;
+    let mut session = RenderSession::new(settings.clone(), scene);
+    session.start();
+    let (mut report, framebuffer) = session.join();
+    report.denoise_time = write_outputs(settings, &framebuffer)?;
+    Ok(report)
+}
@@ -0,0 +1,332 @@
+use std::cell::OnceCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crate::math::*;
+use crate::camera::Camera;
+use crate::settings::RenderSettings;
+use crate::scene::Scene;
+use crate::framebuffer::{Framebuffer, RenderTile};
+use crate::image::Image;
+use crate::render::{image_tiles, render_thread, stop_reason, Pass, RenderReport};
+
+/// How far a render has come.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Progress {
+    /// Number of the current pass, starting at 1.
+    pub pass: usize,
+    /// Tiles of the current pass that are done.
+    pub tiles_rendered: usize,
+    pub tile_count: usize,
+    /// Samples per pixel, averaged over the image.
+    pub average_samples: Float,
+    /// Estimate of the finished part of the render in [0, 1], by samples or, with a time limit, by time.
+    pub fraction: Float,
+    /// Set once the current pass is done; the progress callback is called once more for every pass with this set.
+    pub pass_finished: bool
+}
+
+impl Progress {
+    pub(crate) fn new(pass: usize, tiles_rendered: usize, tile_count: usize, pass_finished: bool) -> Progress {
+        Progress { pass: pass, tiles_rendered: tiles_rendered, tile_count: tile_count, average_samples: 0.0,
+            fraction: 0.0, pass_finished: pass_finished }
+    }
+
+    /// Fills in the sample count and the estimated fraction from `framebuffer`, of a render that started at `start`.
+    pub(crate) fn complete(&mut self, settings: &RenderSettings, framebuffer: &Framebuffer, start: Instant) {
+        self.average_samples = framebuffer.total_samples() as Float / (framebuffer.width() * framebuffer.height()) as Float;
+        self.fraction = self.average_samples / settings.samples as Float;
+        if let Some(limit) = settings.time_limit {
+            self.fraction = self.fraction.max(start.elapsed().as_secs_f32() / limit);
+        }
+        self.fraction = self.fraction.min(1.0);
+    }
+}
+
+/// The framebuffer as seen by the progress callback. It is only copied when the callback asks for all of it, so
+/// that callbacks that need just the progress or the color don't hold up the render threads with a copy.
+pub struct Snapshot<'a> {
+    framebuffer: &'a RwLock<Framebuffer>,
+    copy: OnceCell<Framebuffer>
+}
+
+impl<'a> Snapshot<'a> {
+    pub(crate) fn new(framebuffer: &'a RwLock<Framebuffer>) -> Snapshot<'a> {
+        Snapshot { framebuffer: framebuffer, copy: OnceCell::new() }
+    }
+
+    /// The current color of the pixels, or that of the copy once there is one.
+    pub fn color(&self) -> Image {
+        match self.copy.get() {
+            Some(framebuffer) => framebuffer.color(),
+            None => self.framebuffer.read().unwrap().color()
+        }
+    }
+
+    /// A copy of the framebuffer, taken on the first call.
+    pub fn framebuffer(&self) -> &Framebuffer {
+        self.copy.get_or_init(|| self.framebuffer.read().unwrap().clone())
+    }
+}
+
+pub(crate) type ProgressCallback = Box<dyn FnMut(&Progress, &Snapshot) + Send>;
+pub(crate) type TileCallback = Box<dyn FnMut(&RenderTile) + Send>;
+
+/// Lets the session pause and cancel its render threads.
+pub(crate) struct Control {
+    paused: Mutex<bool>,
+    resumed: Condvar,
+    cancelled: AtomicBool
+}
+
+/// A render running in the background. The session is set up with `new` and the optional callbacks, and started
+/// with `start`; the render threads then take tiles until the render is finished or cancelled, while the session
+/// can be polled for the progress and a snapshot of the framebuffer. Dropping a session cancels the render and
+/// waits for its threads.
+pub struct RenderSession {
+    settings: Arc<RenderSettings>,
+    /// The scene, until the session is started.
+    scene: Option<Scene>,
+    framebuffer: Arc<RwLock<Framebuffer>>,
+    control: Arc<Control>,
+    progress: Arc<Mutex<Progress>>,
+    progress_callback: Option<ProgressCallback>,
+    tile_callback: Option<TileCallback>,
+    /// The thread that runs the passes and the callbacks.
+    coordinator: Option<JoinHandle<RenderReport>>
+}
+
+impl Control {
+    pub fn new() -> Control {
+        Control { paused: Mutex::new(false), resumed: Condvar::new(), cancelled: AtomicBool::new(false) }
+    }
+
+    /// Blocks while the render is paused. Returns false if the render is cancelled.
+    pub fn wait(&self) -> bool {
+        let mut paused = self.paused.lock().unwrap();
+        while *paused && !self.is_cancelled() {
+            paused = self.resumed.wait(paused).unwrap();
+        }
+        !self.is_cancelled()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap()
+    }
+
+    fn set_paused(&self, paused: bool) {
+        *self.paused.lock().unwrap() = paused;
+        self.resumed.notify_all();
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        // taking the lock keeps the notification from slipping in between the check and the wait in `wait`
+        let _paused = self.paused.lock().unwrap();
+        self.resumed.notify_all();
+    }
+}
+
+impl RenderSession {
+    pub fn new(settings: RenderSettings, scene: Scene) -> RenderSession {
+        let framebuffer = Framebuffer::new(settings.width, settings.height, settings.filter());
+        RenderSession { settings: Arc::new(settings), scene: Some(scene), framebuffer: Arc::new(RwLock::new(framebuffer)),
+            control: Arc::new(Control::new()), progress: Arc::new(Mutex::new(Progress::default())),
+            progress_callback: None, tile_callback: None, coordinator: None }
+    }
+
+    /// Creates a session that continues the render in `framebuffer`, e.g. one read from a checkpoint. Pixels that
+    /// already have samples get more, up to `settings.samples`. Fails if the framebuffer has another size or filter
+    /// than `settings`.
+    pub fn with_framebuffer(settings: RenderSettings, scene: Scene, framebuffer: Framebuffer)
+            -> Result<RenderSession, String> {
+        if (framebuffer.width(), framebuffer.height()) != (settings.width, settings.height) {
+            return Err(format!("the framebuffer is {}x{} pixels, but the settings are for {}x{}", framebuffer.width(),
+                framebuffer.height(), settings.width, settings.height));
+        }
+        if framebuffer.filter() != &settings.filter() {
+            return Err(String::from("the framebuffer was rendered with another filter than the settings"));
+        }
+        let mut session = RenderSession::new(settings, scene);
+        session.framebuffer = Arc::new(RwLock::new(framebuffer));
+        Ok(session)
+    }
+
+    /// Calls `callback` after tiles are done, at most once per batch of tiles, and at the end of every pass. It runs
+    /// on the thread of the session that coordinates the passes, while the render threads keep merging their tiles;
+    /// the `Snapshot` reads from the framebuffer only on demand.
+    pub fn on_progress(&mut self, callback: impl FnMut(&Progress, &Snapshot) + Send + 'static) {
+        self.assert_not_started();
+        self.progress_callback = Some(Box::new(callback));
+    }
+
+    /// Calls `callback` with every tile that is done, on the same thread as the progress callback.
+    pub fn on_tile(&mut self, callback: impl FnMut(&RenderTile) + Send + 'static) {
+        self.assert_not_started();
+        self.tile_callback = Some(Box::new(callback));
+    }
+
+    fn assert_not_started(&self) {
+        if self.scene.is_none() {
+            panic!("the render session has already been started");
+        }
+    }
+
+    /// Starts rendering in the background.
+    pub fn start(&mut self) {
+        self.assert_not_started();
+        let coordinator = Coordinator {
+            settings: Arc::clone(&self.settings),
+            scene: Arc::new(self.scene.take().unwrap()),
+            framebuffer: Arc::clone(&self.framebuffer),
+            control: Arc::clone(&self.control),
+            progress: Arc::clone(&self.progress),
+            progress_callback: self.progress_callback.take(),
+            tile_callback: self.tile_callback.take()
+        };
+        self.coordinator = Some(thread::spawn(move || coordinator.run()));
+    }
+
+    pub fn progress(&self) -> Progress {
+        *self.progress.lock().unwrap()
+    }
+
+    /// Returns a copy of the framebuffer as it is now.
+    pub fn snapshot(&self) -> Framebuffer {
+        self.framebuffer.read().unwrap().clone()
+    }
+
+    /// Stops the render threads from taking new tiles; the tiles they are rendering are finished.
+    pub fn pause(&self) {
+        self.control.set_paused(true);
+    }
+
+    pub fn resume(&self) {
+        self.control.set_paused(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control.is_paused()
+    }
+
+    /// Stops the render as soon as possible. Tiles that are not done yet are dropped, so the framebuffer only
+    /// contains whole tiles of every pass.
+    pub fn cancel(&self) {
+        self.control.cancel();
+    }
+
+    /// Returns true once the render has stopped, because it was finished or cancelled.
+    pub fn is_finished(&self) -> bool {
+        self.coordinator.as_ref().is_some_and(|coordinator| coordinator.is_finished())
+    }
+
+    /// Waits for the render to stop and returns its report and framebuffer.
+    pub fn join(mut self) -> (RenderReport, Framebuffer) {
+        let coordinator = match self.coordinator.take() {
+            Some(coordinator) => coordinator,
+            None => panic!("the render session has not been started")
+        };
+        let report = coordinator.join().expect("render threads don't panic");
+        (report, self.snapshot())
+    }
+}
+
+impl Drop for RenderSession {
+    fn drop(&mut self) {
+        if let Some(coordinator) = self.coordinator.take() {
+            self.control.cancel();
+            // the panic of a render thread has already been reported, and panicking in drop would abort
+            let _ = coordinator.join();
+        }
+    }
+}
+
+/// Runs the passes of a session and its callbacks.
+struct Coordinator {
+    settings: Arc<RenderSettings>,
+    scene: Arc<Scene>,
+    framebuffer: Arc<RwLock<Framebuffer>>,
+    control: Arc<Control>,
+    progress: Arc<Mutex<Progress>>,
+    progress_callback: Option<ProgressCallback>,
+    tile_callback: Option<TileCallback>
+}
+
+impl Coordinator {
+    fn run(mut self) -> RenderReport {
+        let settings = Arc::clone(&self.settings);
+        let tiles = image_tiles(&settings);
+        let tile_count = tiles.len();
+
+        let camera = Arc::new(Camera::from_settings(&self.scene.camera,
+            settings.width as Float / settings.height as Float));
+        let start_render = Instant::now();
+        let pass = Pass::new(&settings, start_render);
+
+        // every pass renders all tiles, with fresh render threads that continue from the samples of earlier passes
+        let mut pass_index = 0;
+        let stop_reason = loop {
+            let pass_tiles = Arc::new(Mutex::new(tiles.clone()));
+            let mut thread_handles = Vec::new();
+            let (tx, rx) = mpsc::channel();
+            for _ in 0..settings.threads {
+                let thread_settings = Arc::clone(&settings);
+                let thread_tiles = Arc::clone(&pass_tiles);
+                let thread_scene = Arc::clone(&self.scene);
+                let thread_camera = Arc::clone(&camera);
+                let thread_data = Arc::clone(&self.framebuffer);
+                let thread_control = Arc::clone(&self.control);
+                let thread_tx = tx.clone();
+
+                let handle = thread::spawn(move || {
+                    render_thread(thread_tx, thread_settings, thread_tiles, thread_scene, thread_camera, thread_data,
+                        thread_control, pass);
+                });
+                thread_handles.push(handle);
+            }
+            // the channel closes once all render threads are done, with all tiles, at the deadline or when cancelled
+            drop(tx);
+
+            let mut rendered_tiles = 0;
+            while let Ok(tile) = rx.recv() {
+                // tiles that are done in the meantime are reported together
+                for tile in Some(tile).into_iter().chain(rx.try_iter()) {
+                    rendered_tiles += 1;
+                    if let Some(callback) = &mut self.tile_callback {
+                        callback(&tile);
+                    }
+                }
+                self.report(Progress::new(pass_index + 1, rendered_tiles, tile_count, false), start_render);
+            }
+            for handle in thread_handles {
+                handle.join().unwrap();
+            }
+            pass_index += 1;
+
+            self.report(Progress::new(pass_index, rendered_tiles, tile_count, true), start_render);
+            if self.control.is_cancelled() {
+                break "cancelled";
+            }
+            if let Some(reason) = stop_reason(&settings, &pass, &self.framebuffer.read().unwrap()) {
+                break reason;
+            }
+        };
+        RenderReport::new(start_render.elapsed(), pass_index, stop_reason, &self.framebuffer.read().unwrap())
+    }
+
+    /// Completes `progress` with the state of the framebuffer, stores it for polling and passes it to the callback.
+    fn report(&mut self, mut progress: Progress, start_render: Instant) {
+        progress.complete(&self.settings, &self.framebuffer.read().unwrap(), start_render);
+        *self.progress.lock().unwrap() = progress;
+        if let Some(callback) = &mut self.progress_callback {
+            callback(&progress, &Snapshot::new(&self.framebuffer));
+        }
+    }
+}
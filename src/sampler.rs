@@ -0,0 +1,164 @@
+use std::fmt;
+
+use crate::math::*;
+
+mod stratified;
+mod halton;
+mod sobol;
+pub use crate::sampler::stratified::StratifiedSampler;
+pub use crate::sampler::halton::HaltonSampler;
+pub use crate::sampler::sobol::SobolSampler;
+
+/// Generates the uniform random numbers of the samples of a pixel. Every sample draws its numbers in the same order
+/// (the dimensions), so that the low-discrepancy samplers can distribute each dimension well over the samples of
+/// a pixel. The numbers only depend on the seed, the pixel, the sample index and the dimension, which makes renders
+/// reproducible regardless of the number of threads and the order in which tiles are rendered.
+pub trait Sampler {
+    /// Starts sample `index` of pixel (`x`, `y`); the following numbers belong to its dimensions 0, 1, ...
+    fn start_sample(&mut self, x: u32, y: u32, index: usize);
+    /// Returns the number for the next dimension, in [0, 1).
+    fn get_1d(&mut self) -> Float;
+    /// Returns the numbers for the next two dimensions, which are well distributed as a pair.
+    fn get_2d(&mut self) -> [Float; 2];
+}
+
+/// The available sample generators.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SamplerKind {
+    /// Independent uniform random numbers.
+    Independent,
+    /// Jittered strata, visited in a random order per pixel and dimension.
+    Stratified,
+    /// The Halton sequence, randomly shifted per pixel and dimension.
+    Halton,
+    /// Owen-scrambled Sobol points.
+    Sobol
+}
+
+/// Hashes the seed, pixel, sample index and dimension into an independent uniform number.
+pub struct IndependentSampler {
+    seed: u64,
+    sample: u64,
+    dimension: u64
+}
+
+impl SamplerKind {
+    pub const ALL: [SamplerKind; 4] = [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton,
+        SamplerKind::Sobol];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        SamplerKind::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+
+    /// Creates a sampler for pixels with up to `samples` samples, randomized by `seed`.
+    pub fn create(self, samples: usize, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed))
+        }
+    }
+}
+
+impl fmt::Display for SamplerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler { seed: seed, sample: 0, dimension: 0 }
+    }
+
+    fn next(&mut self) -> u64 {
+        let value = hash(self.sample, self.dimension);
+        self.dimension += 1;
+        value
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: usize) {
+        self.sample = hash(pixel_hash(self.seed, x, y), index as u64);
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Float {
+        to_float(self.next() as u32)
+    }
+
+    fn get_2d(&mut self) -> [Float; 2] {
+        let bits = self.next();
+        [to_float(bits as u32), to_float((bits >> 32) as u32)]
+    }
+}
+
+/// Mixes `value` into the hash `state` (the SplitMix64 finalizer).
+pub fn hash(state: u64, value: u64) -> u64 {
+    let mut z = (state ^ value).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Random bits for value `index` of `dimension` of the scene setup, e.g. the gradients of Perlin noise. Like the
+/// samples they only depend on `seed`, but they are hashed apart from the pixels.
+pub fn scene_random(seed: u64, dimension: u64, index: u64) -> u64 {
+    hash(hash(hash(seed, u64::MAX), dimension), index)
+}
+
+/// Hash of a pixel, from which the samplers derive the randomization of its samples.
+fn pixel_hash(seed: u64, x: u32, y: u32) -> u64 {
+    hash(hash(seed, x as u64), y as u64)
+}
+
+/// Maps the bits of `bits` to [0, 1), keeping as many high bits as a `Float` can hold so the result stays below 1.
+pub(crate) fn to_float(bits: u32) -> Float {
+    (bits >> 8) as Float * (1.0 / (1u32 << 24) as Float)
+}
+
+/// Element `i` of a random permutation of `0..count`, chosen by `seed`; from Kensler, "Correlated Multi-Jittered
+/// Sampling" (2013).
+fn permute(mut i: u32, count: u32, seed: u32) -> u32 {
+    let mut mask = count - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & mask) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & mask) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & mask) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= mask;
+        i ^= i >> 5;
+        if i < count {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % count
+}
@@ -0,0 +1,64 @@
+use crate::math::*;
+use crate::sampler::{hash, permute, pixel_hash, to_float, Sampler};
+
+/// The first primes, the bases of the dimensions of the Halton sequence. Higher dimensions would need larger bases,
+/// whose first points are badly distributed.
+const PRIMES: [u32; 64] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83,
+    89, 97, 101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199,
+    211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311];
+
+/// The Halton sequence, dimension `d` being the radical inverse of the sample index in the `d`th prime base. The
+/// digits are Owen scrambled per pixel and dimension: each is permuted randomly, depending on the digits before it.
+/// Besides decorrelating the pixels this breaks up the linear patterns of the first points of neighbouring large
+/// bases. Dimensions past the available bases are independent random numbers.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: u64,
+    index: usize,
+    dimension: usize
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler { seed: seed, pixel: 0, index: 0, dimension: 0 }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: usize) {
+        self.pixel = pixel_hash(self.seed, x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Float {
+        let dimension_hash = hash(self.pixel, self.dimension as u64);
+        let value = match PRIMES.get(self.dimension) {
+            Some(&base) => scrambled_radical_inverse(self.index as u64, base, dimension_hash),
+            None => to_float(hash(dimension_hash, self.index as u64) as u32)
+        };
+        self.dimension += 1;
+        value
+    }
+
+    fn get_2d(&mut self) -> [Float; 2] {
+        [self.get_1d(), self.get_1d()]
+    }
+}
+
+/// Mirrors the digits of `index` in base `base` at the decimal point, permuting every digit with a permutation
+/// chosen by `seed` and the digits before it. The leading zeros of `index` are permuted as well, until the digits
+/// are below the precision of a `Float`.
+fn scrambled_radical_inverse(mut index: u64, base: u32, seed: u64) -> Float {
+    let (mut digits, mut scale) = (0u64, 1u64);
+    let mut prefix = seed;
+    while scale < 1 << 24 {
+        let digit = (index % base as u64) as u32;
+        digits = digits * base as u64 + permute(digit, base, prefix as u32) as u64;
+        scale *= base as u64;
+        index /= base as u64;
+        prefix = hash(prefix, digit as u64);
+    }
+    // rounding to `Float` can reach 1
+    ((digits as f64 / scale as f64) as Float).min(1.0 - EPSILON / 2.0)
+}
@@ -0,0 +1,75 @@
+use crate::math::*;
+use crate::sampler::{hash, pixel_hash, to_float, Sampler};
+
+/// Owen-scrambled Sobol points, following Burley, "Practical Hash-based Owen Scrambling" (2020). Every pair of
+/// dimensions takes the first two dimensions of the Sobol sequence, which form a (0, 2)-sequence, and scrambles
+/// them with a hash of the pixel and dimension. The pairs are decorrelated from each other by shuffling the sample
+/// index, also with an Owen scramble, which keeps every power of two prefix of the samples well stratified.
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler { seed: seed, pixel: 0, index: 0, dimension: 0 }
+    }
+
+    /// Returns the shuffled sample index and the hash for the scrambles of the next dimensions.
+    fn next_dimensions(&mut self) -> (u32, u64) {
+        let dimension_hash = hash(self.pixel, self.dimension);
+        self.dimension += 1;
+        (nested_uniform_scramble(self.index, dimension_hash as u32), dimension_hash)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: usize) {
+        self.pixel = pixel_hash(self.seed, x, y);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Float {
+        let (index, dimension_hash) = self.next_dimensions();
+        to_float(nested_uniform_scramble(index.reverse_bits(), (dimension_hash >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> [Float; 2] {
+        let (index, dimension_hash) = self.next_dimensions();
+        let scramble = hash(dimension_hash, 1);
+        [to_float(nested_uniform_scramble(index.reverse_bits(), scramble as u32)),
+            to_float(nested_uniform_scramble(sobol_1(index), (scramble >> 32) as u32))]
+    }
+}
+
+/// Dimension 1 of the Sobol sequence; dimension 0 is the bit reversed index.
+fn sobol_1(mut index: u32) -> u32 {
+    let (mut result, mut direction) = (0u32, 1u32 << 31);
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Hash that only lets bits affect more significant bits, from Laine and Karras, "Stratified Sampling for
+/// Stochastic Transparency" (2011), with the constants of Burley.
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+/// Owen scramble of the binary fraction `x`: every bit is flipped depending on the bits above it.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
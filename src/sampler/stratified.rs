@@ -0,0 +1,55 @@
+use crate::math::*;
+use crate::sampler::{hash, permute, pixel_hash, to_float, Sampler};
+
+/// Jittered sampling: every dimension is divided into as many strata as there are samples per pixel (pairs of
+/// dimensions into a grid), and each sample of a pixel takes a random point in a different stratum. The order in
+/// which the strata are visited is a random permutation per pixel and dimension, so that the dimensions are not
+/// correlated and pixels that stop early (adaptive or progressive rendering) still sample without bias.
+pub struct StratifiedSampler {
+    samples: usize,
+    seed: u64,
+    pixel: u64,
+    index: usize,
+    dimension: u64
+}
+
+impl StratifiedSampler {
+    pub fn new(samples: usize, seed: u64) -> StratifiedSampler {
+        StratifiedSampler { samples: samples.max(1), seed: seed, pixel: 0, index: 0, dimension: 0 }
+    }
+
+    /// Returns the stratum of the current sample out of `count`, and a hash for the jitter within it.
+    fn stratum(&mut self, count: usize) -> (usize, u64) {
+        let dimension_hash = hash(self.pixel, self.dimension);
+        self.dimension += 1;
+        // samples beyond the expected count start over with another permutation
+        let round = (self.index / count) as u64;
+        let stratum = permute((self.index % count) as u32, count as u32, hash(dimension_hash, round) as u32);
+        (stratum as usize, hash(dimension_hash, self.index as u64 | 1 << 63))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: usize) {
+        self.pixel = pixel_hash(self.seed, x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Float {
+        let (stratum, jitter) = self.stratum(self.samples);
+        // rounding to `Float` can reach 1
+        ((stratum as Float + to_float(jitter as u32)) / self.samples as Float).min(1.0 - EPSILON / 2.0)
+    }
+
+    fn get_2d(&mut self) -> [Float; 2] {
+        // the grid has at least as many cells as there are samples; with a random order that leaves some unused
+        // cells unbiased
+        let columns = (self.samples as Float).sqrt().ceil() as usize;
+        let rows = self.samples.div_ceil(columns);
+        let (stratum, jitter) = self.stratum(columns * rows);
+        let (x, y) = (stratum % columns, stratum / columns);
+        [((x as Float + to_float(jitter as u32)) / columns as Float).min(1.0 - EPSILON / 2.0),
+            ((y as Float + to_float((jitter >> 32) as u32)) / rows as Float).min(1.0 - EPSILON / 2.0)]
+    }
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use crate::math::*;
+use crate::hitable::*;
+use crate::material::*;
+use crate::texture::*;
+use crate::camera::CameraSettings;
+use crate::settings::*;
+use crate::image::ToneMapOperator;
+use crate::denoise::DenoiseMethod;
+
+mod loader;
+mod obj;
+pub use crate::scene::loader::SceneError;
+
+pub struct Scene {
+    pub textures: Vec<Box<dyn Texture>>,
+    /// Materials referenced by the `material` of hit records.
+    pub materials: Vec<Materials>,
+    pub world: Vec<Arc<dyn Hitable>>,
+    /// Emissive shapes that are sampled directly; they are also part of `world`.
+    pub lights: Vec<Arc<dyn Hitable>>,
+    pub camera: CameraSettings,
+    pub settings: SceneSettings,
+    /// Statistics of the BVH over the bounded shapes, if there are any.
+    pub bvh_stats: Option<BvhStats>,
+    /// Hash of what the scene was built from: the name of a built-in scene, or a scene file and the image and mesh
+    /// files it references. Checkpoints are only resumed with a scene of the same hash.
+    pub hash: u64
+}
+
+/// Render settings stored alongside a scene; these take precedence over the defaults but not over
+/// options given on the command line.
+#[derive(Copy, Clone, Default)]
+pub struct SceneSettings {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples: Option<usize>,
+    pub tone_map: Option<ToneMapOperator>,
+    pub exposure: Option<Float>,
+    pub white_point: Option<Float>,
+    pub denoise: Option<DenoiseMethod>
+}
+
+impl Scene {
+    /// Builds a built-in scene. `seed` chooses the random parts of its setup, like Perlin noise; see
+    /// `RenderSettings::seed`.
+    pub fn new(kind: SceneKind, bvh: &BvhSettings, seed: u64) -> Scene {
+        match kind {
+            SceneKind::CornellBox => cornell_box(bvh, seed),
+            SceneKind::Spheres => spheres(bvh, seed)
+        }
+    }
+
+    pub fn load(source: &SceneSource, bvh: &BvhSettings, seed: u64) -> Result<Scene, SceneError> {
+        match source {
+            SceneSource::Builtin(kind) => Ok(Scene::new(*kind, bvh, seed)),
+            SceneSource::File(path) => loader::load(path, bvh, seed)
+        }
+    }
+}
+
+impl SceneSettings {
+    pub fn apply(&self, settings: &mut RenderSettings) {
+        if let Some(width) = self.width {
+            settings.width = width;
+        }
+        if let Some(height) = self.height {
+            settings.height = height;
+        }
+        if let Some(samples) = self.samples {
+            settings.samples = samples;
+        }
+        if let Some(operator) = self.tone_map {
+            settings.tone_mapping.operator = operator;
+        }
+        if let Some(exposure) = self.exposure {
+            settings.tone_mapping.exposure = exposure;
+        }
+        if let Some(white_point) = self.white_point {
+            settings.tone_mapping.white_point = Some(white_point);
+        }
+        if let Some(method) = self.denoise {
+            settings.denoise = Some(method);
+        }
+    }
+}
+
+fn cornell_box(bvh: &BvhSettings, seed: u64) -> Scene {
+    // textures
+    let wall_texture = Box::new(ConstantTexture::new(Vec3::new(1.0, 1.0, 1.0)));
+    let l_wall_texture = Box::new(ConstantTexture::new(Vec3::new(1.0, 0.0, 0.0)));
+    let r_wall_texture = Box::new(ConstantTexture::new(Vec3::new(0.0, 1.0, 0.0)));
+    let noise_texture = Box::new(NoiseTexture::new(3.0, seed));
+    let light_texture = Box::new(ConstantTexture::new(Vec3::new(5.0, 5.0, 5.0)));
+    let textures: Vec<Box<dyn Texture>> = vec!(wall_texture, l_wall_texture, r_wall_texture, noise_texture, light_texture);
+    // materials
+    let materials = vec!(
+        Materials::Lambertian(Lambertian::new(0)),
+        Materials::Lambertian(Lambertian::new(1)),
+        Materials::Lambertian(Lambertian::new(2)),
+        Materials::Lambertian(Lambertian::new(3)),
+        Materials::DiffuseLight(DiffuseLight::new(4)));
+    let (wall_mat, l_wall_mat, r_wall_mat, noise_mat, light_mat) = (0, 1, 2, 3, 4);
+    // geometry: the walls are instances of a unit square, the light is transformed directly so that it can be sampled
+    let white_quad = build_bvh(&quad(&Transform::identity(), wall_mat), 0.0, 0.0, bvh, seed).0;
+    let red_quad = build_bvh(&quad(&Transform::identity(), l_wall_mat), 0.0, 0.0, bvh, seed).0;
+    let green_quad = build_bvh(&quad(&Transform::identity(), r_wall_mat), 0.0, 0.0, bvh, seed).0;
+    let wall = |quad: &Arc<dyn Hitable>, axis: Vec3, degrees: Float, offset: Vec3| -> Arc<dyn Hitable> {
+        let transform = Transform::scale(Vec3::new(2.0, 2.0, 2.0))
+            .then(&Transform::rotate(axis, degrees))
+            .then(&Transform::translate(offset));
+        Arc::new(Instance::new(Arc::clone(quad), transform))
+    };
+    let back = wall(&white_quad, Vec3::new(0.0, 1.0, 0.0), 0.0, Vec3::new(0.0, 0.0, -2.0));
+    let left = wall(&red_quad, Vec3::new(0.0, 1.0, 0.0), 90.0, Vec3::new(-2.0, 0.0, 0.0));
+    let right = wall(&green_quad, Vec3::new(0.0, 1.0, 0.0), -90.0, Vec3::new(2.0, 0.0, 0.0));
+    let bottom = wall(&white_quad, Vec3::new(1.0, 0.0, 0.0), -90.0, Vec3::new(0.0, -2.0, 0.0));
+    let top = wall(&white_quad, Vec3::new(1.0, 0.0, 0.0), 90.0, Vec3::new(0.0, 2.0, 0.0));
+    let light_transform = Transform::scale(Vec3::new(0.5, 0.5, 0.5))
+        .then(&Transform::rotate(Vec3::new(1.0, 0.0, 0.0), 90.0))
+        .then(&Transform::translate(Vec3::new(0.0, 1.95, 0.0)));
+    let lights = quad(&light_transform, light_mat);
+    // TODO: complete cornell box
+    let sphere1 = Arc::new(Sphere::new(Vec3::new(0.75, -1.25, 1.0), 0.75, noise_mat));
+    let mut bvh_elements: Vec<Arc<dyn Hitable>> = vec!(back, left, right, bottom, top, sphere1);
+    bvh_elements.extend(lights.iter().cloned());
+    let (bvh, bvh_stats) = build_bvh(&tagged(bvh_elements, 1), 0.0, 0.0, bvh, seed);
+    Scene {
+        textures: textures,
+        materials: materials,
+        world: vec!(bvh),
+        lights: lights,
+        camera: CameraSettings::new(Vec3::new(0.0, 0.0, 7.0), Vec3::new(0.0, 0.0, 0.0)),
+        settings: SceneSettings::default(),
+        bvh_stats: Some(bvh_stats),
+        hash: hash_bytes(0, SceneKind::CornellBox.name().as_bytes())
+    }
+}
+
+/// Tags the shapes with consecutive object ids, starting at `first_id`.
+fn tagged(shapes: Vec<Arc<dyn Hitable>>, first_id: ObjectId) -> Vec<Arc<dyn Hitable>> {
+    shapes.into_iter().zip(first_id..).map(|(shape, id)| Arc::new(Tagged::new(shape, id)) as Arc<dyn Hitable>).collect()
+}
+
+/// Two triangles covering the square from (-1, -1, 0) to (1, 1, 0), transformed by `transform`.
+fn quad(transform: &Transform, material: MaterialId) -> Vec<Arc<dyn Hitable>> {
+    let [p0, p1, p2, p3] = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(x, y)| transform.point(Vec3::new(x, y, 0.0)));
+    vec!(Arc::new(Triangle::new(p0, p1, p2, material)), Arc::new(Triangle::new(p0, p2, p3, material)))
+}
+
+fn spheres(bvh: &BvhSettings, seed: u64) -> Scene {
+    let gold_texture = Box::new(ConstantTexture::new(Vec3::new(0.8, 0.6, 0.2)));
+    let ground_texture = Box::new(CheckerTexture::new(5, 6, 4.0 * PI));
+    let wall_texture = Box::new(ConstantTexture::new(Vec3::new(0.6, 0.2, 0.2)));
+    let sphere_texture = Box::new(MarbleTexture::new(7.0, seed));
+    let white_texture = Box::new(ConstantTexture::new(Vec3::new(1.0, 1.0, 1.0)));
+    let ground_even_texture = Box::new(ConstantTexture::new(Vec3::new(0.2, 0.3, 1.0)));
+    let ground_odd_texture = Box::new(ConstantTexture::new(Vec3::new(1.0, 0.3, 0.2)));
+    let textures: Vec<Box<dyn Texture>> = vec!(gold_texture, ground_texture, wall_texture, sphere_texture,
+        white_texture, ground_even_texture, ground_odd_texture);
+    let materials = vec!(
+        Materials::Dielectric(Dielectric::new(1.5)),
+        Materials::Lambertian(Lambertian::new(1)),
+        Materials::Lambertian(Lambertian::new(3)),
+        Materials::Metal(Metal::new(0, 0.8)),
+        Materials::Lambertian(Lambertian::new(2)),
+        Materials::Metal(Metal::new(4, 0.0)));
+    let (mat1, mat2, mat3, gold, mat5, mirror) = (0, 1, 2, 3, 4, 5);
+    let sphere1 = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, -1.0), 0.5, mat3));
+    let sphere2 = Arc::new(Sphere::new(Vec3::new(-1.0, 0.0, -1.0), 0.49, mat1));
+    let sphere3 = Arc::new(Sphere::new(Vec3::new(1.0, 0.0, -1.0), 0.5, gold));
+    let ground = Arc::new(Plane::new(Vec3::new(0.0, 1.0, 0.0), -0.501, mat2));
+    let wall = Arc::new(Plane::new(Vec3::new(0.0, 0.0, 1.0), -2.0, mat5));
+    let sphere5 = Arc::new(Sphere::new(Vec3::new(-1.0, 0.0, -1.0), -0.45, mat1));
+    let tri1 = Arc::new(Triangle::new(Vec3::new(2.0, 0.0, -2.0),
+        Vec3::new(2.0, 1.5, -1.5), Vec3::new(-2.0, 0.0, -2.0), mirror));
+    let tri2 = Arc::new(Triangle::new(Vec3::new(2.0, 1.5, -1.5),
+        Vec3::new(-2.0, 1.5, -1.5), Vec3::new(-2.0, 0.0, -2.0), mirror));
+    let bvh_elements = tagged(vec!(sphere1, sphere2, sphere3, sphere5, tri1, tri2), 1);
+    let mut world = tagged(vec!(ground, wall), bvh_elements.len() as ObjectId + 1);
+    let (bvh, bvh_stats) = build_bvh(&bvh_elements, 0.0, 0.0, bvh, seed);
+    world.push(bvh);
+    Scene {
+        textures: textures,
+        materials: materials,
+        world: world,
+        lights: Vec::new(),
+        camera: CameraSettings::new(Vec3::new(-3.0, 1.0, 3.0), Vec3::new(0.0, 0.0, -1.0)),
+        settings: SceneSettings::default(),
+        bvh_stats: Some(bvh_stats),
+        hash: hash_bytes(0, SceneKind::Spheres.name().as_bytes())
+    }
+}
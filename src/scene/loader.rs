@@ -0,0 +1,606 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::math::*;
+use crate::hitable::*;
+use crate::material::*;
+use crate::texture::*;
+use crate::camera::CameraSettings;
+use crate::scene::{Scene, SceneSettings};
+use crate::scene::obj::load_obj;
+use crate::image::ToneMapOperator;
+use crate::denoise::DenoiseMethod;
+use crate::sampler::hash;
+
+/// An error while loading a scene file. `line` is 0 for errors that do not belong to a specific line.
+#[derive(Debug)]
+pub struct SceneError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+        } else {
+            write!(f, "{}: {}", self.path.display(), self.message)
+        }
+    }
+}
+
+/// A single non-empty line of a scene file, split into its keyword, positional arguments and `key=value` fields.
+struct Line<'a> {
+    keyword: &'a str,
+    args: Vec<&'a str>,
+    fields: Vec<(&'a str, &'a str)>
+}
+
+impl<'a> Line<'a> {
+    fn parse(text: &'a str) -> Result<Option<Line<'a>>, String> {
+        let text = match text.find('#') {
+            Some(index) => &text[..index],
+            None => text
+        };
+        let mut tokens = text.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => return Ok(None)
+        };
+        let mut line = Line { keyword: keyword, args: Vec::new(), fields: Vec::new() };
+        for token in tokens {
+            if let Some(index) = token.find('=') {
+                let (key, value) = (&token[..index], &token[index + 1..]);
+                if key.is_empty() || value.is_empty() {
+                    return Err(format!("malformed field '{}', expected key=value", token));
+                }
+                if line.fields.iter().any(|(k, _)| *k == key) {
+                    return Err(format!("field '{}' is given more than once", key));
+                }
+                line.fields.push((key, value));
+            } else if line.fields.is_empty() {
+                line.args.push(token);
+            } else {
+                return Err(format!("unexpected '{}' after key=value fields", token));
+            }
+        }
+        Ok(Some(line))
+    }
+
+    fn arg(&self, index: usize, what: &str) -> Result<&'a str, String> {
+        match self.args.get(index) {
+            Some(arg) => Ok(arg),
+            None => Err(format!("'{}' is missing its {}", self.keyword, what))
+        }
+    }
+
+    fn optional(&mut self, key: &str) -> Option<&'a str> {
+        let index = self.fields.iter().position(|(k, _)| *k == key)?;
+        Some(self.fields.remove(index).1)
+    }
+
+    fn field(&mut self, key: &str) -> Result<&'a str, String> {
+        match self.optional(key) {
+            Some(value) => Ok(value),
+            None => Err(format!("missing field '{}' for '{}'", key, self.keyword))
+        }
+    }
+
+    fn float(&mut self, key: &str) -> Result<Float, String> {
+        let value = self.field(key)?;
+        parse_float(key, value)
+    }
+
+    fn optional_float(&mut self, key: &str) -> Result<Option<Float>, String> {
+        self.optional(key).map(|value| parse_float(key, value)).transpose()
+    }
+
+    fn vec3(&mut self, key: &str) -> Result<Vec3, String> {
+        let value = self.field(key)?;
+        parse_vec3(key, value)
+    }
+
+    fn optional_vec3(&mut self, key: &str) -> Result<Option<Vec3>, String> {
+        self.optional(key).map(|value| parse_vec3(key, value)).transpose()
+    }
+
+    fn optional_bool(&mut self, key: &str) -> Result<Option<bool>, String> {
+        match self.optional(key) {
+            Some("true") => Ok(Some(true)),
+            Some("false") => Ok(Some(false)),
+            Some(value) => Err(format!("field '{}' must be true or false, got '{}'", key, value)),
+            None => Ok(None)
+        }
+    }
+
+    fn optional_uv(&mut self, key: &str) -> Result<Option<[Float; 2]>, String> {
+        match self.optional(key) {
+            Some(value) => {
+                let components: Vec<&str> = value.split(',').collect();
+                if components.len() != 2 {
+                    return Err(format!("field '{}' must be a texture coordinate of the form u,v, got '{}'", key, value));
+                }
+                Ok(Some([parse_float(key, components[0])?, parse_float(key, components[1])?]))
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn optional_count<T: std::str::FromStr + Default + PartialEq>(&mut self, key: &str) -> Result<Option<T>, String> {
+        match self.optional(key) {
+            Some(value) => match value.parse::<T>() {
+                Ok(number) if number != T::default() => Ok(Some(number)),
+                _ => Err(format!("field '{}' must be a positive integer, got '{}'", key, value))
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// Fails if the line has arguments or fields that were not consumed by the directive.
+    fn finish(self, arg_count: usize) -> Result<(), String> {
+        if let Some(arg) = self.args.get(arg_count) {
+            return Err(format!("unexpected argument '{}' for '{}'", arg, self.keyword));
+        }
+        match self.fields.first() {
+            Some((key, _)) => Err(format!("unknown field '{}' for '{}'", key, self.keyword)),
+            None => Ok(())
+        }
+    }
+}
+
+fn parse_float(key: &str, value: &str) -> Result<Float, String> {
+    match value.parse::<Float>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(format!("field '{}' must be a number, got '{}'", key, value))
+    }
+}
+
+fn parse_vec3(key: &str, value: &str) -> Result<Vec3, String> {
+    let components: Vec<&str> = value.split(',').collect();
+    if components.len() != 3 {
+        return Err(format!("field '{}' must be a vector of the form x,y,z, got '{}'", key, value));
+    }
+    Ok(Vec3::new(parse_float(key, components[0])?, parse_float(key, components[1])?, parse_float(key, components[2])?))
+}
+
+/// Resolves a reference to a previously defined item, given either by name or by index.
+fn resolve(kind: &str, reference: &str, names: &HashMap<String, usize>, count: usize) -> Result<usize, String> {
+    if let Some(index) = names.get(reference) {
+        Ok(*index)
+    } else if let Ok(index) = reference.parse::<usize>() {
+        if index < count {
+            Ok(index)
+        } else {
+            Err(format!("{} index {} is out of range ({} {}s defined so far)", kind, index, count, kind))
+        }
+    } else {
+        Err(format!("unknown {} '{}'", kind, reference))
+    }
+}
+
+/// A sphere or instance that may still receive keyframes from the `key` lines following it.
+enum Animated {
+    Sphere { center: Keyframes<Vec3>, radius: Float, material: MaterialId },
+    Instance { object: usize, poses: Keyframes<Pose> }
+}
+
+#[derive(Default)]
+struct SceneBuilder {
+    /// Directory that relative paths in the scene file are resolved against.
+    directory: PathBuf,
+    textures: Vec<Box<dyn Texture>>,
+    texture_names: HashMap<String, usize>,
+    materials: Vec<Materials>,
+    material_names: HashMap<String, usize>,
+    bounded: Vec<Arc<dyn Hitable>>,
+    unbounded: Vec<Arc<dyn Hitable>>,
+    lights: Vec<Arc<dyn Hitable>>,
+    objects: Vec<Arc<dyn Hitable>>,
+    object_names: HashMap<String, usize>,
+    /// Number of object ids handed out to the shapes of the scene so far.
+    object_ids: ObjectId,
+    /// Name and shapes of the object that is being defined, if any.
+    current_object: Option<(String, Vec<Arc<dyn Hitable>>)>,
+    /// The last sphere or instance, which is added once a line other than `key` is read.
+    animated: Option<Animated>,
+    camera: Option<CameraSettings>,
+    settings: SceneSettings,
+    bvh: BvhSettings,
+    /// Chooses the random parts of the scene, like Perlin noise.
+    seed: u64,
+    /// Hash of the scene file and the files it references so far.
+    hash: u64
+}
+
+impl SceneBuilder {
+    /// Adds the contents of `file` to the hash of the scene. Files that can't be read are skipped, the error is
+    /// reported by the code that loads them.
+    fn hash_file(&mut self, file: &Path) {
+        if let Ok(bytes) = fs::read(file) {
+            self.hash = hash_bytes(self.hash, &bytes);
+        }
+    }
+
+    fn texture(&self, line: &mut Line, key: &str) -> Result<TextureId, String> {
+        let reference = line.field(key)?;
+        resolve("texture", reference, &self.texture_names, self.textures.len())
+    }
+
+    fn material(&self, line: &mut Line) -> Result<MaterialId, String> {
+        let reference = line.field("material")?;
+        resolve("material", reference, &self.material_names, self.materials.len())
+    }
+
+    /// Adds a shape to the object being defined or to the scene BVH. Emissive shapes that are not part of an
+    /// object are also sampled as lights.
+    fn add_bounded(&mut self, shape: Arc<dyn Hitable>, material: MaterialId) {
+        if let (None, Materials::DiffuseLight(_)) = (&self.current_object, self.materials[material]) {
+            self.lights.push(Arc::clone(&shape));
+        }
+        self.add_shapes(vec!(shape));
+    }
+
+    /// Adds the shapes created by a single directive to the object being defined, or tags them with a new object
+    /// id and adds them to the bounded shapes of the scene.
+    fn add_shapes(&mut self, shapes: Vec<Arc<dyn Hitable>>) {
+        match &mut self.current_object {
+            Some((_, object)) => object.extend(shapes),
+            None => {
+                let id = self.next_object_id();
+                self.bounded.extend(shapes.into_iter().map(|shape| Arc::new(Tagged::new(shape, id)) as Arc<dyn Hitable>));
+            }
+        }
+    }
+
+    fn next_object_id(&mut self) -> ObjectId {
+        self.object_ids += 1;
+        self.object_ids
+    }
+
+    /// Parses the optional `scale`, `rotate` and `translate` fields. `scale` is either a single factor or one per
+    /// axis, `rotate` gives angles in degrees around the x, y and z axis.
+    fn pose(line: &mut Line) -> Result<Pose, String> {
+        let mut pose = Pose::identity();
+        if let Some(value) = line.optional("scale") {
+            pose.scale = match parse_float("scale", value) {
+                Ok(factor) => Vec3::new(factor, factor, factor),
+                Err(_) => parse_vec3("scale", value)?
+            };
+            if pose.scale.x() == 0.0 || pose.scale.y() == 0.0 || pose.scale.z() == 0.0 {
+                return Err(String::from("scale factors must not be zero"));
+            }
+        }
+        pose.rotate = line.optional_vec3("rotate")?.unwrap_or(pose.rotate);
+        pose.translate = line.optional_vec3("translate")?.unwrap_or(pose.translate);
+        Ok(pose)
+    }
+
+    /// Adds the last sphere or instance to the scene. Shapes without keyframes are added as static shapes, moving
+    /// spheres are not sampled as lights.
+    fn finish_animated(&mut self) {
+        match self.animated.take() {
+            Some(Animated::Sphere { center, radius, material }) if center.len() == 1 => {
+                self.add_bounded(Arc::new(Sphere::new(center.last().1, radius, material)), material);
+            },
+            Some(Animated::Sphere { center, radius, material }) => {
+                self.add_shapes(vec!(Arc::new(MovingSphere::new(center, radius, material))));
+            },
+            Some(Animated::Instance { object, poses }) if poses.len() == 1 => {
+                let instance = Instance::new(Arc::clone(&self.objects[object]), poses.last().1.transform());
+                self.add_shapes(vec!(Arc::new(instance)));
+            },
+            Some(Animated::Instance { object, poses }) => {
+                let instance = MovingInstance::new(Arc::clone(&self.objects[object]), poses);
+                self.add_shapes(vec!(Arc::new(instance)));
+            },
+            None => ()
+        }
+    }
+
+    /// Parses a `key` line, which adds a keyframe to the preceding sphere or instance.
+    fn keyframe(&mut self, line: &mut Line) -> Result<(), String> {
+        let time = line.float("time")?;
+        let last_time = match &self.animated {
+            Some(Animated::Sphere { center, .. }) => center.last().0,
+            Some(Animated::Instance { poses, .. }) => poses.last().0,
+            None => return Err(String::from("'key' must follow a sphere, an instance or another 'key'"))
+        };
+        if time <= last_time {
+            return Err(format!("keyframe time {} must be after the previous keyframe at time {}", time, last_time));
+        }
+        match &mut self.animated {
+            Some(Animated::Sphere { center, .. }) => center.add(time, line.vec3("center")?),
+            Some(Animated::Instance { poses, .. }) => {
+                let pose = SceneBuilder::pose(line)?;
+                let previous = poses.last().1.scale;
+                if (0..3).any(|dim| pose.scale[dim].signum() != previous[dim].signum()) {
+                    return Err(String::from("scale factors can not change their sign between keyframes"));
+                }
+                poses.add(time, pose);
+            },
+            None => unreachable!()
+        }
+        Ok(())
+    }
+
+    fn define(names: &mut HashMap<String, usize>, kind: &str, name: &str, index: usize) -> Result<(), String> {
+        if name.parse::<usize>().is_ok() {
+            return Err(format!("{} name '{}' must not be a number", kind, name));
+        }
+        if names.insert(name.to_string(), index).is_some() {
+            return Err(format!("{} '{}' is already defined", kind, name));
+        }
+        Ok(())
+    }
+
+    fn parse_line(&mut self, mut line: Line) -> Result<(), String> {
+        let arg_count = match line.keyword {
+            "texture" | "material" => 2,
+            "object" => 1,
+            _ => 0
+        };
+        if line.keyword != "key" {
+            self.finish_animated();
+        }
+        match line.keyword {
+            "texture" => {
+                let name = line.arg(0, "name")?;
+                let kind = line.arg(1, "type")?;
+                // every noise texture gets its own gradients
+                let noise_seed = hash(self.seed, self.textures.len() as u64);
+                let texture: Box<dyn Texture> = match kind {
+                    "constant" => Box::new(ConstantTexture::new(line.vec3("color")?)),
+                    "checker" => {
+                        let even = self.texture(&mut line, "even")?;
+                        let odd = self.texture(&mut line, "odd")?;
+                        Box::new(CheckerTexture::new(even, odd, line.float("frequency")?))
+                    },
+                    "uv_checker" => {
+                        let even = self.texture(&mut line, "even")?;
+                        let odd = self.texture(&mut line, "odd")?;
+                        Box::new(UvCheckerTexture::new(even, odd, line.float("frequency")?))
+                    },
+                    "image" => {
+                        let file = self.directory.join(line.field("file")?);
+                        self.hash_file(&file);
+                        let wrap = match line.optional("wrap") {
+                            Some("repeat") | None => WrapMode::Repeat,
+                            Some("clamp") => WrapMode::Clamp,
+                            Some("mirror") => WrapMode::Mirror,
+                            Some(value) => return Err(format!("field 'wrap' must be repeat, clamp or mirror, got '{}'", value))
+                        };
+                        let filter = match line.optional("filter") {
+                            Some("bilinear") | None => FilterMode::Bilinear,
+                            Some("nearest") => FilterMode::Nearest,
+                            Some(value) => return Err(format!("field 'filter' must be nearest or bilinear, got '{}'", value))
+                        };
+                        let srgb = line.optional_bool("srgb")?.unwrap_or(true);
+                        match ImageTexture::open(&file, wrap, filter, srgb) {
+                            Ok(texture) => Box::new(texture),
+                            Err(why) => return Err(format!("couldn't load image {}: {}", file.display(), why))
+                        }
+                    },
+                    "noise" => Box::new(NoiseTexture::new(line.float("frequency")?, noise_seed)),
+                    "marble" => Box::new(MarbleTexture::new(line.float("frequency")?, noise_seed)),
+                    _ => return Err(format!("unknown texture type '{}', expected one of: constant, checker, uv_checker, image, noise, marble", kind))
+                };
+                SceneBuilder::define(&mut self.texture_names, "texture", name, self.textures.len())?;
+                self.textures.push(texture);
+            },
+            "material" => {
+                let name = line.arg(0, "name")?;
+                let kind = line.arg(1, "type")?;
+                let material = match kind {
+                    "lambertian" => Materials::Lambertian(Lambertian::new(self.texture(&mut line, "albedo")?)),
+                    "metal" => Materials::Metal(Metal::new(self.texture(&mut line, "albedo")?, line.float("roughness")?)),
+                    "dielectric" => Materials::Dielectric(Dielectric::new(line.float("ior")?)),
+                    "diffuse_light" => Materials::DiffuseLight(DiffuseLight::new(self.texture(&mut line, "emit")?)),
+                    _ => return Err(format!(
+                        "unknown material type '{}', expected one of: lambertian, metal, dielectric, diffuse_light", kind))
+                };
+                SceneBuilder::define(&mut self.material_names, "material", name, self.materials.len())?;
+                self.materials.push(material);
+            },
+            "sphere" => {
+                let material = self.material(&mut line)?;
+                let center = Keyframes::new(0.0, line.vec3("center")?);
+                self.animated = Some(Animated::Sphere { center: center, radius: line.float("radius")?, material: material });
+            },
+            "plane" => {
+                let normal = line.vec3("normal")?;
+                if normal.length_squared() <= EPSILON {
+                    return Err(String::from("plane normal must not be zero"));
+                }
+                if self.current_object.is_some() {
+                    return Err(String::from("planes can not be part of an object"));
+                }
+                let plane = Plane::new(normal, line.float("distance")?, self.material(&mut line)?);
+                let id = self.next_object_id();
+                self.unbounded.push(Arc::new(Tagged::new(Arc::new(plane), id)));
+            },
+            "triangle" => {
+                let (p0, p1, p2) = (line.vec3("p0")?, line.vec3("p1")?, line.vec3("p2")?);
+                let uvs = match (line.optional_uv("uv0")?, line.optional_uv("uv1")?, line.optional_uv("uv2")?) {
+                    (Some(uv0), Some(uv1), Some(uv2)) => Some([uv0, uv1, uv2]),
+                    (None, None, None) => None,
+                    _ => return Err(String::from("either all or none of 'uv0', 'uv1' and 'uv2' must be given"))
+                };
+                let material = self.material(&mut line)?;
+                let triangle = Triangle::with_attributes(p0, p1, p2, None, uvs, material);
+                self.add_bounded(Arc::new(triangle), material);
+            },
+            "mesh" => {
+                let file = self.directory.join(line.field("file")?);
+                self.hash_file(&file);
+                let material = self.material(&mut line)?;
+                let use_mtl = line.optional_bool("mtl")?.unwrap_or(true);
+                // "mesh" keeps the mesh as a single primitive with its own BVH, "scene" adds each triangle to the scene BVH
+                let separate = match line.optional("accel") {
+                    Some("mesh") | None => false,
+                    Some("scene") => true,
+                    Some(value) => return Err(format!("field 'accel' must be mesh or scene, got '{}'", value))
+                };
+                let model = load_obj(&file, material, use_mtl, &mut self.textures, &mut self.materials, &mut self.hash)
+                    .map_err(|why| why.to_string())?;
+                let ranges = match line.optional("group") {
+                    Some(group) => {
+                        let ranges: Vec<_> = model.groups.iter().filter(|(name, _)| name == group).map(|(_, range)| range.clone()).collect();
+                        if ranges.is_empty() {
+                            return Err(format!("{} has no group named '{}'", file.display(), group));
+                        }
+                        ranges
+                    },
+                    None => std::iter::once(0..model.triangle_count()).collect()
+                };
+                let mut shapes = Vec::new();
+                for range in ranges {
+                    if self.current_object.is_none() {
+                        self.lights.extend(model.light_triangles(range.clone(), &self.materials));
+                    }
+                    if separate {
+                        shapes.extend(model.triangles(range));
+                    } else {
+                        shapes.extend(model.meshes(range, &self.bvh, self.seed).into_iter().map(|mesh| Arc::new(mesh) as Arc<dyn Hitable>));
+                    }
+                }
+                self.add_shapes(shapes);
+            },
+            "object" => {
+                let name = line.arg(0, "name")?;
+                if let Some((current, _)) = &self.current_object {
+                    return Err(format!("objects can not be nested, '{}' is missing its 'end'", current));
+                }
+                SceneBuilder::define(&mut self.object_names, "object", name, self.objects.len())?;
+                self.current_object = Some((name.to_string(), Vec::new()));
+            },
+            "end" => {
+                let (name, shapes) = match self.current_object.take() {
+                    Some(object) => object,
+                    None => return Err(String::from("'end' without 'object'"))
+                };
+                if shapes.is_empty() {
+                    return Err(format!("object '{}' contains no shapes", name));
+                }
+                // the shutter is not known yet if the camera comes later, so the bounds cover all keyframes
+                self.objects.push(build_bvh(&shapes, MIN_FLOAT, MAX_FLOAT, &self.bvh, self.seed).0);
+            },
+            "instance" => {
+                let reference = line.field("object")?;
+                let index = resolve("object", reference, &self.object_names, self.objects.len())?;
+                let poses = Keyframes::new(0.0, SceneBuilder::pose(&mut line)?);
+                self.animated = Some(Animated::Instance { object: index, poses: poses });
+            },
+            "key" => self.keyframe(&mut line)?,
+            "camera" => {
+                if self.camera.is_some() {
+                    return Err(String::from("camera is already defined"));
+                }
+                let mut camera = CameraSettings::new(line.vec3("from")?, line.vec3("at")?);
+                camera.up = line.optional_vec3("up")?.unwrap_or(camera.up);
+                camera.vertical_fov = line.optional_float("fov")?.unwrap_or(camera.vertical_fov);
+                camera.aperture = line.optional_float("aperture")?.unwrap_or(camera.aperture);
+                camera.focus_dist = line.optional_float("focus")?;
+                if let Some(value) = line.optional("shutter") {
+                    let times: Vec<&str> = value.split(',').collect();
+                    if times.len() != 2 {
+                        return Err(format!("field 'shutter' must be of the form open,close, got '{}'", value));
+                    }
+                    camera.shutter_open = parse_float("shutter", times[0])?;
+                    camera.shutter_close = parse_float("shutter", times[1])?;
+                    if camera.shutter_close < camera.shutter_open {
+                        return Err(String::from("the shutter must not close before it opens"));
+                    }
+                }
+                self.camera = Some(camera);
+            },
+            "render" => {
+                self.settings.width = line.optional_count("width")?.or(self.settings.width);
+                self.settings.height = line.optional_count("height")?.or(self.settings.height);
+                self.settings.samples = line.optional_count("samples")?.or(self.settings.samples);
+                if let Some(value) = line.optional("tonemap") {
+                    self.settings.tone_map = match ToneMapOperator::from_name(value) {
+                        Some(operator) => Some(operator),
+                        None => {
+                            let operators: Vec<&str> = ToneMapOperator::ALL.iter().map(|operator| operator.name()).collect();
+                            return Err(format!("field 'tonemap' must be one of: {}, got '{}'", operators.join(", "), value));
+                        }
+                    };
+                }
+                self.settings.exposure = line.optional_float("exposure")?.or(self.settings.exposure);
+                if let Some(white_point) = line.optional_float("white")? {
+                    if white_point <= 0.0 {
+                        return Err(String::from("field 'white' must be greater than 0"));
+                    }
+                    self.settings.white_point = Some(white_point);
+                }
+                if let Some(value) = line.optional("denoise") {
+                    self.settings.denoise = match DenoiseMethod::from_name(value) {
+                        Some(method) => Some(method),
+                        None => {
+                            let methods: Vec<&str> = DenoiseMethod::ALL.iter().map(|method| method.name()).collect();
+                            return Err(format!("field 'denoise' must be one of: {}, got '{}'", methods.join(", "), value));
+                        }
+                    };
+                }
+            },
+            keyword => return Err(format!("unknown directive '{}'", keyword))
+        }
+        line.finish(arg_count)
+    }
+
+    fn build(mut self) -> Result<Scene, String> {
+        self.finish_animated();
+        if let Some((name, _)) = &self.current_object {
+            return Err(format!("object '{}' is missing its 'end'", name));
+        }
+        let camera = match self.camera {
+            Some(camera) => camera,
+            None => return Err(String::from("scene has no camera"))
+        };
+        if self.bounded.is_empty() && self.unbounded.is_empty() {
+            return Err(String::from("scene contains no shapes"));
+        }
+        let mut world = self.unbounded;
+        let mut bvh_stats = None;
+        if !self.bounded.is_empty() {
+            let (bvh, stats) = build_bvh(&self.bounded, camera.shutter_open, camera.shutter_close, &self.bvh,
+                self.seed);
+            world.push(bvh);
+            bvh_stats = Some(stats);
+        }
+        Ok(Scene {
+            textures: self.textures, materials: self.materials, world: world, lights: self.lights, camera: camera, settings: self.settings,
+            bvh_stats: bvh_stats, hash: self.hash
+        })
+    }
+}
+
+/// Parses a scene description. See `scenes/cornell.scene` for an example of the format. `seed` chooses the random
+/// parts of the scene.
+pub fn parse(path: &Path, source: &str, bvh: &BvhSettings, seed: u64) -> Result<Scene, SceneError> {
+    let mut builder = SceneBuilder {
+        directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        bvh: *bvh,
+        seed: seed,
+        hash: hash_bytes(0, source.as_bytes()),
+        ..SceneBuilder::default()
+    };
+    for (index, text) in source.lines().enumerate() {
+        let error = |message| SceneError { path: path.to_path_buf(), line: index + 1, message: message };
+        if let Some(line) = Line::parse(text).map_err(error)? {
+            builder.parse_line(line).map_err(error)?;
+        }
+    }
+    builder.build().map_err(|message| SceneError { path: path.to_path_buf(), line: 0, message: message })
+}
+
+pub fn load(path: &Path, bvh: &BvhSettings, seed: u64) -> Result<Scene, SceneError> {
+    match fs::read_to_string(path) {
+        Ok(source) => parse(path, &source, bvh, seed),
+        Err(why) => Err(SceneError { path: path.to_path_buf(), line: 0, message: format!("couldn't read file: {}", why) })
+    }
+}
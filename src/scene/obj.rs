@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::math::*;
+use crate::hitable::*;
+use crate::material::*;
+use crate::texture::*;
+use crate::scene::SceneError;
+
+/// Indexed geometry loaded from a Wavefront OBJ file, with all polygons triangulated.
+pub struct ObjModel {
+    positions: Vec<Vec3>,
+    texcoords: Vec<[Float; 2]>,
+    normals: Vec<Vec3>,
+    /// Scene materials used by the faces; the first one is the default material.
+    materials: Vec<MaterialId>,
+    faces: Vec<ObjTriangle>,
+    /// Named groups (`g` and `o` statements) and the range of triangles they cover.
+    pub groups: Vec<(String, Range<usize>)>
+}
+
+/// Indices into the position, texture coordinate and normal lists of a single face corner.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+struct FaceVertex {
+    position: usize,
+    texcoord: Option<usize>,
+    normal: Option<usize>
+}
+
+struct ObjTriangle {
+    corners: [FaceVertex; 3],
+    /// Index into `ObjModel::materials`.
+    material: usize
+}
+
+impl ObjModel {
+    pub fn triangle_count(&self) -> usize {
+        self.faces.len()
+    }
+
+    /// Creates a separate `Triangle` for each face in `range`, ready to be passed to `build_bvh`.
+    pub fn triangles(&self, range: Range<usize>) -> Vec<Arc<dyn Hitable>> {
+        self.faces[range].iter().map(|face| self.triangle(face)).collect()
+    }
+
+    /// Creates a `Triangle` for each face in `range` that has an emissive material, for use as scene lights.
+    /// `materials` are the materials of the scene.
+    pub fn light_triangles(&self, range: Range<usize>, materials: &[Materials]) -> Vec<Arc<dyn Hitable>> {
+        self.faces[range].iter()
+            .filter(|face| matches!(materials[self.materials[face.material]], Materials::DiffuseLight(_)))
+            .map(|face| self.triangle(face))
+            .collect()
+    }
+
+    fn triangle(&self, face: &ObjTriangle) -> Arc<dyn Hitable> {
+        let [p0, p1, p2] = face.corners.map(|corner| self.positions[corner.position]);
+        let material = self.materials[face.material];
+        let normals = match face.corners.map(|corner| corner.normal) {
+            [Some(n0), Some(n1), Some(n2)] => Some([self.normals[n0], self.normals[n1], self.normals[n2]]),
+            _ => None
+        };
+        let uvs = match face.corners.map(|corner| corner.texcoord) {
+            [Some(t0), Some(t1), Some(t2)] => Some([self.texcoords[t0], self.texcoords[t1], self.texcoords[t2]]),
+            _ => None
+        };
+        Arc::new(Triangle::with_attributes(p0, p1, p2, normals, uvs, material))
+    }
+
+    /// Creates one `TriangleMesh` per material used by the faces in `range`, with BVHs built by `bvh` and `seed`.
+    /// Face corners that share the same position, texture coordinate and normal become a single mesh vertex.
+    pub fn meshes(&self, range: Range<usize>, bvh: &BvhSettings, seed: u64) -> Vec<TriangleMesh> {
+        let mut meshes = Vec::new();
+        for material in 0..self.materials.len() {
+            let faces: Vec<&ObjTriangle> = self.faces[range.clone()].iter().filter(|face| face.material == material).collect();
+            if faces.is_empty() {
+                continue;
+            }
+            let corners = || faces.iter().flat_map(|face| face.corners.iter());
+            let has_normals = corners().all(|corner| corner.normal.is_some());
+            let has_texcoords = corners().all(|corner| corner.texcoord.is_some());
+
+            let mut vertices: HashMap<FaceVertex, u32> = HashMap::new();
+            let (mut positions, mut normals, mut uvs) = (Vec::new(), Vec::new(), Vec::new());
+            let indices = faces.iter().map(|face| face.corners.map(|corner| {
+                *vertices.entry(corner).or_insert_with(|| {
+                    positions.push(self.positions[corner.position]);
+                    if let (true, Some(normal)) = (has_normals, corner.normal) {
+                        normals.push(self.normals[normal]);
+                    }
+                    if let (true, Some(texcoord)) = (has_texcoords, corner.texcoord) {
+                        uvs.push(self.texcoords[texcoord]);
+                    }
+                    positions.len() as u32 - 1
+                })
+            })).collect();
+            meshes.push(TriangleMesh::new(positions, normals, uvs, indices, self.materials[material], bvh, seed));
+        }
+        meshes
+    }
+}
+
+fn error(path: &Path, line: usize, message: String) -> SceneError {
+    SceneError { path: path.to_path_buf(), line: line, message: message }
+}
+
+fn parse_floats(keyword: &str, args: &[&str], count: usize) -> Result<Vec<Float>, String> {
+    if args.len() < count {
+        return Err(format!("'{}' needs at least {} components", keyword, count));
+    }
+    args.iter().take(count).map(|arg| match arg.parse::<Float>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(format!("invalid number '{}' in '{}'", arg, keyword))
+    }).collect()
+}
+
+/// Resolves a 1-based (or negative, relative) OBJ index into an index into a list of `count` elements.
+fn resolve_index(what: &str, token: &str, count: usize) -> Result<usize, String> {
+    let index = match token.parse::<i64>() {
+        Ok(index) => index,
+        Err(_) => return Err(format!("invalid {} index '{}'", what, token))
+    };
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        Err(format!("{} index {} is out of range ({} defined so far)", what, index, count))
+    } else {
+        Ok(resolved as usize)
+    }
+}
+
+fn parse_face_vertex(token: &str, positions: usize, texcoords: usize, normals: usize) -> Result<FaceVertex, String> {
+    let mut parts = token.split('/');
+    let position = resolve_index("vertex", parts.next().unwrap_or(""), positions)?;
+    let texcoord = match parts.next().filter(|part| !part.is_empty()) {
+        Some(texcoord) => Some(resolve_index("texture coordinate", texcoord, texcoords)?),
+        None => None
+    };
+    let normal = match parts.next().filter(|part| !part.is_empty()) {
+        Some(normal) => Some(resolve_index("normal", normal, normals)?),
+        None => None
+    };
+    if parts.next().is_some() {
+        return Err(format!("malformed face vertex '{}'", token));
+    }
+    Ok(FaceVertex { position: position, texcoord: texcoord, normal: normal })
+}
+
+fn max_component(v: Vec3) -> Float {
+    v.x().max(v.y()).max(v.z())
+}
+
+#[derive(Default)]
+struct MtlMaterial {
+    diffuse: Option<Vec3>,
+    specular: Option<Vec3>,
+    emission: Option<Vec3>,
+    shininess: Option<Float>,
+    refractive_index: Option<Float>,
+    dissolve: Option<Float>,
+    illum: Option<u32>,
+    diffuse_map: Option<String>
+}
+
+impl MtlMaterial {
+    /// Maps the Phong-style MTL parameters onto the closest of the available materials. The contents of the
+    /// texture, if any, are added to `hash`.
+    fn to_material(&self, directory: &Path, textures: &mut Vec<Box<dyn Texture>>, hash: &mut u64)
+            -> Result<Materials, String> {
+        if let Some(map) = &self.diffuse_map {
+            let file = directory.join(map);
+            if let Ok(bytes) = fs::read(&file) {
+                *hash = hash_bytes(*hash, &bytes);
+            }
+            match ImageTexture::open(&file, WrapMode::Repeat, FilterMode::Bilinear, true) {
+                Ok(texture) => textures.push(Box::new(texture)),
+                Err(why) => return Err(format!("couldn't load image {}: {}", file.display(), why))
+            }
+            return Ok(Materials::Lambertian(Lambertian::new(textures.len() - 1)));
+        }
+        let mut add_texture = |color: Vec3| {
+            textures.push(Box::new(ConstantTexture::new(color)));
+            textures.len() - 1
+        };
+        let diffuse = self.diffuse.unwrap_or_else(|| Vec3::new(0.8, 0.8, 0.8));
+        let specular = self.specular.unwrap_or_else(Vec3::zero);
+        let transparent = matches!(self.illum, Some(4) | Some(6) | Some(7)) || self.dissolve.is_some_and(|d| d < 1.0);
+        Ok(if let Some(emission) = self.emission.filter(|e| max_component(*e) > 0.0) {
+            Materials::DiffuseLight(DiffuseLight::new(add_texture(emission)))
+        } else if transparent {
+            Materials::Dielectric(Dielectric::new(self.refractive_index.unwrap_or(1.5)))
+        } else if max_component(specular) > max_component(diffuse) {
+            // convert the Phong exponent into a roughness in [0, 1]
+            let roughness = (2.0 / (self.shininess.unwrap_or(0.0).max(0.0) + 2.0)).sqrt();
+            Materials::Metal(Metal::new(add_texture(specular), roughness))
+        } else {
+            Materials::Lambertian(Lambertian::new(add_texture(diffuse)))
+        })
+    }
+}
+
+fn parse_color(keyword: &str, args: &[&str]) -> Result<Vec3, String> {
+    if args.len() >= 3 {
+        let values = parse_floats(keyword, args, 3)?;
+        Ok(Vec3::new(values[0], values[1], values[2]))
+    } else {
+        let value = parse_floats(keyword, args, 1)?[0];
+        Ok(Vec3::new(value, value, value))
+    }
+}
+
+fn parse_mtl_statement(material: &mut MtlMaterial, keyword: &str, args: &[&str]) -> Result<(), String> {
+    match keyword {
+        "Kd" => material.diffuse = Some(parse_color(keyword, args)?),
+        "Ks" => material.specular = Some(parse_color(keyword, args)?),
+        "Ke" => material.emission = Some(parse_color(keyword, args)?),
+        "Ns" => material.shininess = Some(parse_floats(keyword, args, 1)?[0]),
+        "Ni" => material.refractive_index = Some(parse_floats(keyword, args, 1)?[0]),
+        "d" => material.dissolve = Some(parse_floats(keyword, args, 1)?[0]),
+        "Tr" => material.dissolve = Some(1.0 - parse_floats(keyword, args, 1)?[0]),
+        "illum" => material.illum = Some(parse_floats(keyword, args, 1)?[0] as u32),
+        // the file name comes after any options
+        "map_Kd" => match args.last() {
+            Some(file) => material.diffuse_map = Some(file.to_string()),
+            None => return Err(String::from("'map_Kd' is missing the file name"))
+        },
+        // the other texture maps and the remaining parameters are not supported
+        _ => ()
+    }
+    Ok(())
+}
+
+/// Loads the materials of an MTL library, adding the contents of the library and its textures to `hash`.
+fn load_mtl(path: &Path, textures: &mut Vec<Box<dyn Texture>>, hash: &mut u64)
+        -> Result<Vec<(String, Materials)>, SceneError> {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(why) => return Err(error(path, 0, format!("couldn't read file: {}", why)))
+    };
+    *hash = hash_bytes(*hash, source.as_bytes());
+    let mut parsed: Vec<(String, usize, MtlMaterial)> = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let text = text.split('#').next().unwrap_or("");
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+        let (keyword, args) = (tokens[0], &tokens[1..]);
+        if keyword == "newmtl" {
+            match args.first() {
+                Some(name) => parsed.push((name.to_string(), line, MtlMaterial::default())),
+                None => return Err(error(path, line, String::from("'newmtl' is missing the material name")))
+            }
+        } else if let Some((_, _, material)) = parsed.last_mut() {
+            parse_mtl_statement(material, keyword, args).map_err(|message| error(path, line, message))?;
+        }
+    }
+    let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    parsed.into_iter().map(|(name, line, material)| match material.to_material(&directory, textures, hash) {
+        Ok(material) => Ok((name, material)),
+        Err(message) => Err(error(path, line, message))
+    }).collect()
+}
+
+/// Loads the geometry of an OBJ file. Polygons are triangulated as fans.
+///
+/// If `use_mtl` is set, the material libraries referenced by `mtllib` are read, their materials and textures are
+/// added to `materials` and `textures` and assigned to the faces following `usemtl`. Faces without a material
+/// use `default_material`. The contents of the material libraries and their textures are added to `hash`, see
+/// `Scene::hash`.
+pub fn load_obj(path: &Path, default_material: MaterialId, use_mtl: bool, textures: &mut Vec<Box<dyn Texture>>,
+        materials: &mut Vec<Materials>, hash: &mut u64) -> Result<ObjModel, SceneError> {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(why) => return Err(error(path, 0, format!("couldn't read file: {}", why)))
+    };
+    let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    let mut model = ObjModel {
+        positions: Vec::new(), texcoords: Vec::new(), normals: Vec::new(), materials: vec!(default_material),
+        faces: Vec::new(), groups: Vec::new()
+    };
+    let mut material_names: HashMap<String, usize> = HashMap::new();
+    let mut material = 0;
+    let mut group: Option<(String, usize)> = None;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let text = text.split('#').next().unwrap_or("");
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+        let (keyword, args) = (tokens[0], &tokens[1..]);
+        match keyword {
+            "v" => {
+                let v = parse_floats(keyword, args, 3).map_err(|message| error(path, line, message))?;
+                model.positions.push(Vec3::new(v[0], v[1], v[2]));
+            },
+            "vt" => {
+                // the optional third (w) component is ignored
+                let t = parse_floats(keyword, args, args.len().clamp(1, 2)).map_err(|message| error(path, line, message))?;
+                model.texcoords.push([t[0], t.get(1).copied().unwrap_or(0.0)]);
+            },
+            "vn" => {
+                let n = parse_floats(keyword, args, 3).map_err(|message| error(path, line, message))?;
+                model.normals.push(Vec3::new(n[0], n[1], n[2]));
+            },
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error(path, line, String::from("a face needs at least 3 vertices")));
+                }
+                let face = args.iter()
+                    .map(|token| parse_face_vertex(token, model.positions.len(), model.texcoords.len(), model.normals.len()))
+                    .collect::<Result<Vec<FaceVertex>, String>>()
+                    .map_err(|message| error(path, line, message))?;
+                for i in 1..(face.len() - 1) {
+                    let corners = [face[0], face[i], face[i + 1]];
+                    let [p0, p1, p2] = corners.map(|corner| model.positions[corner.position]);
+                    // skip degenerate triangles, they can never be hit
+                    if Vec3::cross(p1 - p0, p2 - p0).length_squared() > 0.0 {
+                        model.faces.push(ObjTriangle { corners: corners, material: material });
+                    }
+                }
+            },
+            "g" | "o" => {
+                if let Some((name, start)) = group.take() {
+                    model.groups.push((name, start..model.faces.len()));
+                }
+                group = Some((args.join(" "), model.faces.len()));
+            },
+            "mtllib" if use_mtl => {
+                for library in args {
+                    for (name, mtl_material) in load_mtl(&directory.join(library), textures, hash)? {
+                        material_names.insert(name, model.materials.len());
+                        model.materials.push(materials.len());
+                        materials.push(mtl_material);
+                    }
+                }
+            },
+            "usemtl" if use_mtl => {
+                material = match args.first().and_then(|name| material_names.get(*name)) {
+                    Some(index) => *index,
+                    None => return Err(error(path, line, format!("unknown material '{}'", args.join(" "))))
+                };
+            },
+            // smoothing groups, lines, free-form geometry etc. are not supported and ignored
+            _ => ()
+        }
+    }
+    if let Some((name, start)) = group {
+        model.groups.push((name, start..model.faces.len()));
+    }
+    if model.faces.is_empty() {
+        return Err(error(path, 0, String::from("file contains no faces")));
+    }
+    Ok(model)
+}
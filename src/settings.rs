@@ -0,0 +1,185 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::ffi::OsStr;
+
+use crate::hitable::BvhSettings;
+use crate::adaptive::AdaptiveSampling;
+use crate::aov::Aov;
+use crate::denoise::DenoiseMethod;
+use crate::sampler::SamplerKind;
+use crate::filter::{Filter, FilterKind};
+use crate::image::{ExrPixel, ToneMapping};
+
+/// The built-in scenes that can be selected for rendering.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SceneKind {
+    CornellBox,
+    Spheres
+}
+
+/// Where the scene to be rendered comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneSource {
+    Builtin(SceneKind),
+    File(PathBuf)
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    /// Samples per pixel; the maximum with adaptive sampling.
+    pub samples: usize,
+    /// Relative error at which adaptive sampling stops sampling a pixel; `None` disables adaptive sampling.
+    pub adaptive_threshold: Option<f32>,
+    /// Samples every pixel gets before adaptive sampling may stop.
+    pub min_samples: usize,
+    /// Samples per pixel of each progressive pass; see `RenderSettings::pass_samples`.
+    pub pass_samples: Option<usize>,
+    /// Wall-clock time in seconds after which no more tiles are started.
+    pub time_limit: Option<f32>,
+    /// Mean relative error of the pixels at which the render stops, checked after every pass.
+    pub noise_target: Option<f32>,
+    /// Generator of the random numbers of the pixel samples.
+    pub sampler: SamplerKind,
+    /// Reconstruction filter the samples are splatted with.
+    pub filter: FilterKind,
+    /// Radius of the filter in pixels; `None` uses the default radius of the filter.
+    pub filter_radius: Option<f32>,
+    pub threads: usize,
+    pub tile_size: u32,
+    pub output: PathBuf,
+    /// Optional floating point copy of the output; the format follows from the file extension.
+    pub hdr_output: Option<PathBuf>,
+    pub exr_pixel: ExrPixel,
+    /// Conversion of the rendered radiance to the PNG output.
+    pub tone_mapping: ToneMapping,
+    /// Optional floating point file for the AOVs; the format follows from the file extension.
+    pub aov_output: Option<PathBuf>,
+    /// AOVs that are written to `aov_output`.
+    pub aovs: Vec<Aov>,
+    /// Filter applied to the finished render, if any.
+    pub denoise: Option<DenoiseMethod>,
+    /// Scales the color tolerance of the denoiser; larger values blur more.
+    pub denoise_strength: f32,
+    /// Optional image of the number of samples taken per pixel; PNG or one of the floating point formats.
+    pub spp_output: Option<PathBuf>,
+    /// File the state of the render is saved to periodically and at the end, so that it can be resumed.
+    pub checkpoint: Option<PathBuf>,
+    /// Seconds of rendering between checkpoints.
+    pub checkpoint_interval: f32,
+    /// Checkpoint the render continues from.
+    pub resume: Option<PathBuf>,
+    /// Address on which to wait for workers, which then render the image instead of this process.
+    pub listen: Option<String>,
+    /// Address of a coordinator to render tiles for, instead of rendering an image.
+    pub worker: Option<String>,
+    pub scene: SceneSource,
+    pub seed: u64,
+    pub bvh: BvhSettings,
+    /// Print statistics about the scene BVH after loading the scene.
+    pub bvh_stats: bool
+}
+
+impl SceneKind {
+    pub const ALL: [SceneKind; 2] = [SceneKind::CornellBox, SceneKind::Spheres];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SceneKind::CornellBox => "cornell",
+            SceneKind::Spheres => "spheres"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SceneKind> {
+        SceneKind::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+}
+
+impl fmt::Display for SceneKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl SceneSource {
+    /// Interprets `value` as the name of a built-in scene or, if it has a `.scene` extension, a scene file.
+    pub fn from_arg(value: &str) -> Option<SceneSource> {
+        let path = PathBuf::from(value);
+        if path.extension() == Some(OsStr::new("scene")) {
+            Some(SceneSource::File(path))
+        } else {
+            SceneKind::from_name(value).map(SceneSource::Builtin)
+        }
+    }
+}
+
+impl fmt::Display for SceneSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneSource::Builtin(kind) => write!(f, "{}", kind),
+            SceneSource::File(path) => write!(f, "{}", path.display())
+        }
+    }
+}
+
+/// Samples per pass when stopping on time or noise without an explicit pass size.
+const DEFAULT_PASS_SAMPLES: usize = 16;
+
+impl RenderSettings {
+    /// Samples per pixel of each pass. Without progressive rendering all samples are taken in a single pass, unless
+    /// the render may be stopped early by a time limit or noise target.
+    pub fn pass_samples(&self) -> usize {
+        match self.pass_samples {
+            Some(samples) => samples,
+            None if self.time_limit.is_some() || self.noise_target.is_some() => DEFAULT_PASS_SAMPLES,
+            None => self.samples
+        }
+    }
+
+    pub fn adaptive(&self) -> Option<AdaptiveSampling> {
+        self.adaptive_threshold.map(|threshold| AdaptiveSampling::new(threshold, self.min_samples))
+    }
+
+    pub fn filter(&self) -> Filter {
+        Filter::new(self.filter, self.filter_radius.unwrap_or_else(|| self.filter.default_radius()))
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 128,
+            height: 128,
+            samples: 200,
+            adaptive_threshold: None,
+            min_samples: 16,
+            pass_samples: None,
+            time_limit: None,
+            noise_target: None,
+            sampler: SamplerKind::Sobol,
+            filter: FilterKind::Box,
+            filter_radius: None,
+            threads: 8,
+            tile_size: 32,
+            output: PathBuf::from("out/out.png"),
+            hdr_output: None,
+            exr_pixel: ExrPixel::Float,
+            tone_mapping: ToneMapping::default(),
+            aov_output: None,
+            aovs: Aov::ALL.to_vec(),
+            denoise: None,
+            denoise_strength: 1.0,
+            spp_output: None,
+            checkpoint: None,
+            checkpoint_interval: 60.0,
+            resume: None,
+            listen: None,
+            worker: None,
+            scene: SceneSource::Builtin(SceneKind::CornellBox),
+            seed: 0,
+            bvh: BvhSettings::default(),
+            bvh_stats: false
+        }
+    }
+}
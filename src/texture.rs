@@ -3,7 +3,9 @@ use std::sync::{Arc, RwLock};
 use crate::math::*;
 
 mod perlin;
+mod image;
 use crate::texture::perlin::PerlinNoise;
+pub use crate::texture::image::{ImageTexture, WrapMode, FilterMode};
 
 pub type TextureId = usize;
 
@@ -21,6 +23,13 @@ pub struct CheckerTexture {
     frequency: Float
 }
 
+/// Checker pattern in texture space, alternating `frequency` times per unit of `u` and `v`.
+pub struct UvCheckerTexture {
+    even: TextureId,
+    odd: TextureId,
+    frequency: Float
+}
+
 pub struct NoiseTexture {
     frequency: Float,
     noise: Arc<RwLock<PerlinNoise>>
@@ -43,15 +52,21 @@ impl CheckerTexture {
     }
 }
 
+impl UvCheckerTexture {
+    pub fn new(even: TextureId, odd: TextureId, frequency: Float) -> UvCheckerTexture {
+        UvCheckerTexture { even: even, odd: odd, frequency: frequency }
+    }
+}
+
 impl NoiseTexture {
-    pub fn new(frequency: Float) -> NoiseTexture {
-        NoiseTexture { frequency: frequency, noise: Arc::new(RwLock::new(PerlinNoise::new())) }
+    pub fn new(frequency: Float, seed: u64) -> NoiseTexture {
+        NoiseTexture { frequency: frequency, noise: Arc::new(RwLock::new(PerlinNoise::new(seed))) }
     }
 }
 
 impl MarbleTexture {
-    pub fn new(frequency: Float) -> MarbleTexture {
-        MarbleTexture { frequency: frequency, noise: Arc::new(RwLock::new(PerlinNoise::new())) }
+    pub fn new(frequency: Float, seed: u64) -> MarbleTexture {
+        MarbleTexture { frequency: frequency, noise: Arc::new(RwLock::new(PerlinNoise::new(seed))) }
     }
 }
 
@@ -75,6 +90,17 @@ impl Texture for CheckerTexture {
     }
 }
 
+impl Texture for UvCheckerTexture {
+    fn value(&self, u: Float, v: Float, p: &Vec3, textures: &[Box<dyn Texture>]) -> Vec3 {
+        let cell = (u * self.frequency).floor() as i64 + (v * self.frequency).floor() as i64;
+        if cell.rem_euclid(2) == 1 {
+            textures[self.odd].value(u, v, p, textures)
+        } else {
+            textures[self.even].value(u, v, p, textures)
+        }
+    }
+}
+
 impl Texture for NoiseTexture {
     fn value(&self, _u: Float, _v: Float, p: &Vec3, _textures: &[Box<dyn Texture>]) -> Vec3 {
         let scaled = self.frequency * p;
@@ -0,0 +1,124 @@
+use std::fs::File;
+use std::path::Path;
+
+use crate::math::*;
+use crate::texture::Texture;
+
+/// How texture coordinates outside of [0, 1] are mapped onto the image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterMode {
+    Nearest,
+    Bilinear
+}
+
+/// A texture backed by an image, stored as linear RGB.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    texels: Vec<Vec3>,
+    wrap: WrapMode,
+    filter: FilterMode
+}
+
+fn srgb_to_linear(value: Float) -> Float {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl WrapMode {
+    fn apply(self, index: i64, size: usize) -> usize {
+        let size = size as i64;
+        let wrapped = match self {
+            WrapMode::Repeat => index.rem_euclid(size),
+            WrapMode::Clamp => index.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let m = index.rem_euclid(2 * size);
+                if m >= size { 2 * size - 1 - m } else { m }
+            }
+        };
+        wrapped as usize
+    }
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, texels: Vec<Vec3>, wrap: WrapMode, filter: FilterMode) -> ImageTexture {
+        if width == 0 || height == 0 || texels.len() != width * height {
+            panic!("image textures need width * height texels.");
+        }
+        ImageTexture { width: width, height: height, texels: texels, wrap: wrap, filter: filter }
+    }
+
+    /// Decodes a PNG file of any bit depth and color type. If `srgb` is set, the values are converted from
+    /// sRGB to linear, which is what color textures need; data textures should pass `false`. Alpha is ignored.
+    pub fn open(path: &Path, wrap: WrapMode, filter: FilterMode, srgb: bool) -> Result<ImageTexture, png::DecodingError> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        // expand palettes and sub-byte gray values to 8 bits per channel, 16-bit images stay 16-bit
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        let (width, height) = (info.width as usize, info.height as usize);
+
+        let channels = info.color_type.samples();
+        let bytes_per_sample = if info.bit_depth == png::BitDepth::Sixteen { 2 } else { 1 };
+        let max_value = if bytes_per_sample == 2 { 65535.0 } else { 255.0 };
+        let sample = |row: &[u8], index: usize| -> Float {
+            let value = if bytes_per_sample == 2 {
+                u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]) as Float
+            } else {
+                row[index] as Float
+            };
+            let value = value / max_value;
+            if srgb { srgb_to_linear(value) } else { value }
+        };
+
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = &buffer[y * info.line_size..(y + 1) * info.line_size];
+            for x in 0..width {
+                let first = x * channels;
+                texels.push(match info.color_type {
+                    png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                        let gray = sample(row, first);
+                        Vec3::new(gray, gray, gray)
+                    },
+                    _ => Vec3::new(sample(row, first), sample(row, first + 1), sample(row, first + 2))
+                });
+            }
+        }
+        Ok(ImageTexture::new(width, height, texels, wrap, filter))
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        self.texels[self.wrap.apply(y, self.height) * self.width + self.wrap.apply(x, self.width)]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: Float, v: Float, _p: &Vec3, _textures: &[Box<dyn Texture>]) -> Vec3 {
+        // v = 0 is the bottom row of the image
+        let x = u * self.width as Float;
+        let y = (1.0 - v) * self.height as Float;
+        match self.filter {
+            FilterMode::Nearest => self.texel(x.floor() as i64, y.floor() as i64),
+            FilterMode::Bilinear => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                (1.0 - fy) * ((1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0)) +
+                    fy * ((1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1))
+            }
+        }
+    }
+}
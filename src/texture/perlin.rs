@@ -1,5 +1,5 @@
 use crate::math::*;
-use rand::prelude::*;
+use crate::sampler::{scene_random, to_float};
 
 pub struct PerlinNoise {
     random: [Vec3; 256],
@@ -8,21 +8,21 @@ pub struct PerlinNoise {
     z_permutation: [u8; 256],
 }
 
-fn permute(input: &mut [u8; 256]) {
+fn permute(input: &mut [u8; 256], seed: u64, dimension: u64) {
     for i in (1..256).rev() {
-        let target = thread_rng().gen_range(0..i);
+        let target = (scene_random(seed, dimension, i as u64) % i as u64) as usize;
         let tmp = input[target];
         input[target] = input[i];
         input[i] = tmp;
     }
 }
 
-fn generate_permutation() -> [u8; 256] {
+fn generate_permutation(seed: u64, dimension: u64) -> [u8; 256] {
     let mut result = [0; 256];
     for i in 0..256 {
         result[i] = i as u8;
     }
-    permute(&mut result);
+    permute(&mut result, seed, dimension);
     result
 }
 
@@ -51,15 +51,19 @@ fn perlin_interpolation(c: &[Vec3; 8], u: Float, v: Float, w: Float) -> Float {
 }
 
 impl PerlinNoise {
-    pub fn new() -> PerlinNoise {
+    /// Creates the noise with the gradients and permutations chosen by `seed`.
+    pub fn new(seed: u64) -> PerlinNoise {
         let mut random = [Vec3::zero(); 256];
-        let x_perm = generate_permutation();
-        let y_perm = generate_permutation();
-        let z_perm = generate_permutation();
+        let x_perm = generate_permutation(seed, 0);
+        let y_perm = generate_permutation(seed, 1);
+        let z_perm = generate_permutation(seed, 2);
+        let uniform = |dimension: u64, index: u64| to_float(scene_random(seed, dimension, index) as u32);
+        let mut index = 0;
         for i in 0..256 {
             let mut v = Vec3::zero();
             while v.length_squared() < 0.0001 {
-                v = 2.0 * Vec3::new(rand::thread_rng().gen(), rand::thread_rng().gen(), rand::thread_rng().gen()) - Vec3::one()
+                v = 2.0 * Vec3::new(uniform(3, index), uniform(4, index), uniform(5, index)) - Vec3::one();
+                index += 1;
             }
 
             random[i] = Vec3::normalize(v);
@@ -0,0 +1,68 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RENDER: &[&str] = &["--width", "48", "--height", "48", "--samples", "16", "--tile-size", "8", "--seed", "7"];
+
+fn rusty_pt() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_rusty-pt"))
+}
+
+fn outputs(directory: &Path, name: &str) -> Vec<String> {
+    let output = directory.join(format!("{}.png", name));
+    let hdr_output = directory.join(format!("{}.pfm", name));
+    vec!(String::from("--output"), output.display().to_string(), String::from("--hdr-output"),
+        hdr_output.display().to_string())
+}
+
+fn worker(address: &str) -> Child {
+    rusty_pt().args(["--worker", address, "--threads", "1"]).stdout(Stdio::null()).spawn().unwrap()
+}
+
+/// Renders with a coordinator and two workers on localhost, one of which is killed once the first tiles are done,
+/// and expects the same image as a local render.
+#[test]
+fn lost_worker_gives_local_image() {
+    let directory: PathBuf = env::temp_dir().join(format!("rusty-pt-distributed-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    let status = rusty_pt().args(RENDER).args(outputs(&directory, "local")).stdout(Stdio::null()).status().unwrap();
+    assert!(status.success());
+
+    let mut coordinator = rusty_pt().args(RENDER).args(outputs(&directory, "distributed"))
+        .args(["--listen", "127.0.0.1:0"]).stdout(Stdio::piped()).spawn().unwrap();
+    let mut stdout = BufReader::new(coordinator.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    let address = line.trim().strip_prefix("waiting for workers on ").expect("coordinator didn't listen").to_string();
+    // read while rendering, or the coordinator blocks once the pipe is full
+    let output = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| String::from_utf8_lossy(&output).into_owned())
+    });
+
+    let mut lost = worker(&address);
+    let mut kept = worker(&address);
+    // the PNG is written after every batch of tiles
+    let start = Instant::now();
+    while !directory.join("distributed.png").exists() && start.elapsed() < Duration::from_secs(60) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    lost.kill().unwrap();
+    lost.wait().unwrap();
+
+    assert!(coordinator.wait().unwrap().success());
+    // workers serve one render after the other until they are stopped
+    kept.kill().unwrap();
+    kept.wait().unwrap();
+    let output = output.join().unwrap().unwrap();
+    assert!(output.contains(" lost ("), "the coordinator didn't lose a worker:\n{}", output);
+    let local = fs::read(directory.join("local.pfm")).unwrap();
+    let distributed = fs::read(directory.join("distributed.pfm")).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+    assert!(local == distributed, "the distributed render differs from the local one");
+}
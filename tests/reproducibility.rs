@@ -0,0 +1,32 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const RENDER: &[&str] = &["--width", "40", "--height", "36", "--samples", "8", "--seed", "11"];
+
+/// Renders with the common settings and `args`, and returns the PFM output.
+fn render(directory: &Path, name: &str, args: &[&str]) -> Vec<u8> {
+    let output = directory.join(format!("{}.png", name));
+    let hdr_output = directory.join(format!("{}.pfm", name));
+    let status = Command::new(env!("CARGO_BIN_EXE_rusty-pt")).args(RENDER).args(args)
+        .arg("--output").arg(&output).arg("--hdr-output").arg(&hdr_output)
+        .stdout(Stdio::null()).status().unwrap();
+    assert!(status.success());
+    fs::read(&hdr_output).unwrap()
+}
+
+/// Renders with the same seed but different thread counts and tile sizes, and expects identical images.
+#[test]
+fn threads_and_tiles_give_same_image() {
+    let directory = env::temp_dir().join(format!("rusty-pt-reproducibility-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    let one_thread = render(&directory, "threads-1", &["--threads", "1"]);
+    let five_threads = render(&directory, "threads-5", &["--threads", "5"]);
+    let small_tiles = render(&directory, "tiles-7", &["--tile-size", "7"]);
+    let large_tiles = render(&directory, "tiles-13", &["--tile-size", "13"]);
+    fs::remove_dir_all(&directory).unwrap();
+    assert!(one_thread == five_threads, "the render with 5 threads differs from the one with 1");
+    assert!(small_tiles == large_tiles, "the render with tiles of 13 pixels differs from the one with 7");
+}